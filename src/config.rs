@@ -1,11 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs;
-use std::sync::Arc;
 
 use lazy_static::lazy_static;
-use num_cpus;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Semaphore;
 
 // pub static STDIN_FILENAME: &str = "stdin.txt";
 pub static STDOUT_FILENAME: &str = "stdout.txt";
@@ -17,6 +16,27 @@ pub static SPJ_ANSWER_FILENAME: &str = "spj_answer.txt";
 pub static SPJ_STDOUT_FILENAME: &str = "spj_stdout.txt";
 pub static SPJ_STDERR_FILENAME: &str = "spj_stderr.txt";
 pub static SPJ_RESULT_FILENAME: &str = "spj_result.txt";
+// 每个评测请求的临时目录中，编译、运行与 spj 各自使用独立的子目录
+pub static BUILD_DIRNAME: &str = "build";
+pub static RUN_DIRNAME: &str = "run";
+pub static CHECKER_DIRNAME: &str = "checker";
+// 程序的 stdout、stderr 与运行结果保存在工作目录之外的此目录中，沙盒内的程序无法将它们替换为符号链接
+pub static OUTPUT_DIRNAME: &str = "output";
+// 未配置 checker_cache 时，编译后的 spj 缓存在 judge_dir 下的此目录中
+pub static CHECKER_CACHE_DIRNAME: &str = "checkers";
+// 未指定时差异详情中每段文本的最大字节数
+pub static DETAIL_LIMIT: usize = 1024;
+// 运行错误时返回的 stderr 末尾部分的最大字节数
+pub static STDERR_TAIL_LIMIT: usize = 2048;
+// 一个测试点最多运行的次数（包括第一次），rerun.runs 更大时按此值处理
+pub static MAX_RUNS: i32 = 5;
+// 未指定时 spj 的 CPU 时间限制（ms）与内存限制（KiB）
+pub static SPJ_TIME_LIMIT: i32 = 5000;
+pub static SPJ_MEMORY_LIMIT: i32 = 1024 * 1024;
+// memory_mode 为 rss 时沙盒实际施加的内存上限（KiB）
+pub static RSS_MEMORY_CEILING: i32 = 1024 * 1024;
+// memory_mode 为 address_space 时，程序异常退出且内存峰值达到内存限制的此百分比即视为超出内存限制
+pub static ADDRESS_SPACE_MLE_PERCENT: i64 = 90;
 
 lazy_static! {
     pub static ref CONFIG: Config = {
@@ -25,20 +45,149 @@ lazy_static! {
         debug!("{:?}", cfg);
         cfg
     };
-    pub static ref CPU_SEMAPHORE: Arc<Semaphore> = {
-        let num = num_cpus::get();
-        info!("cpus = {}", num);
-        // 设置最大并发量与 CPU 核数相同，以防止因资源不足而产生系统错误
-        Arc::new(Semaphore::new(num))
-    };
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct LanguageConf {
-    pub compile_cmd: String,
+    pub compile_cmd: Command,
     pub code_file: String,
-    pub run_cmd: String,
+    pub run_cmd: Command,
     pub version: String,
+    // 运行时在默认白名单之外额外允许的系统调用，例如 JVM 与 Go 需要的 clone、sched_getaffinity 等
+    #[serde(default)]
+    pub syscalls: Vec<String>,
+    // 为 false 时运行不使用 seccomp 过滤，用于系统调用难以穷举的运行时
+    #[serde(default = "default_seccomp")]
+    pub seccomp: bool,
+    #[serde(default)]
+    pub memory_mode: MemoryMode,
+    // 编译与运行时额外设置的环境变量，会覆盖 Config.env 中的同名变量
+    #[serde(default)]
+    pub compile_env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub run_env: BTreeMap<String, String>,
+    // 代替 Config.rootfs，使每种语言可以使用只包含自己工具链的镜像
+    #[serde(default)]
+    pub rootfs: Option<String>,
+    // 以只读 overlay 的方式叠加在 rootfs 之上的目录，越靠前的层优先级越高
+    #[serde(default)]
+    pub overlay: Vec<String>,
+}
+
+/// 编译或运行的命令
+///
+/// 配置中既可以写成字符串，按照 shell 的规则拆分（支持引号与转义，但不会展开变量），
+/// 也可以直接写成参数列表。加载配置时拒绝无法解析或为空的命令。
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(try_from = "CommandConf", into = "Vec<String>")]
+pub struct Command(Vec<String>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CommandConf {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        match shell_words::split(line) {
+            Ok(args) => Command::new(args),
+            Err(e) => Err(format!("invalid command `{}`: {}", line, e)),
+        }
+    }
+
+    pub fn new(args: Vec<String>) -> Result<Command, String> {
+        match args.first() {
+            Some(program) if !program.is_empty() => Ok(Command(args)),
+            _ => Err(format!("invalid command {:?}: empty program", args)),
+        }
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl TryFrom<CommandConf> for Command {
+    type Error = String;
+
+    fn try_from(conf: CommandConf) -> Result<Self, Self::Error> {
+        match conf {
+            CommandConf::Line(line) => Command::parse(&line),
+            CommandConf::Args(args) => Command::new(args),
+        }
+    }
+}
+
+impl From<Command> for Vec<String> {
+    fn from(command: Command) -> Self {
+        command.0
+    }
+}
+
+// 按 shell 的规则转义后拼接，用于展示
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", shell_words::join(&self.0))
+    }
+}
+
+/// 内存限制的方式，内存的单位均为 KiB
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryMode {
+    // 由 cgroup 限制程序实际使用的内存，超出时被 OOM killer 杀死，没有 cgroup 时退化为 address_space
+    #[default]
+    Cgroup,
+    // 使用 RLIMIT_AS 限制虚拟地址空间，超出时内存分配失败，异常退出且内存峰值接近限制时判定为 MLE
+    AddressSpace,
+    // 按峰值常驻内存（RSS）判定，运行时只施加 RSS_MEMORY_CEILING 的上限，适用于 JVM 等预留大量地址空间的运行时
+    Rss,
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxKind {
+    // 进程内实现的沙盒
+    #[default]
+    Native,
+    // 外部的 newbie-sandbox 程序
+    Newbie,
+    // 直接在本机运行，不做隔离，仅用于开发与测试
+    Local,
+}
+
+fn default_newbie_sandbox() -> String {
+    String::from("./newbie-sandbox/target/x86_64-unknown-linux-gnu/release/newbie-sandbox")
+}
+
+fn default_sandbox_id() -> u32 {
+    // nobody
+    65534
+}
+
+fn default_seccomp() -> bool {
+    true
+}
+
+fn default_cpus_per_run() -> usize {
+    1
+}
+
+fn default_message_limit() -> usize {
+    16 * 1024
+}
+
+fn default_env() -> BTreeMap<String, String> {
+    [
+        ("PATH", "/usr/local/bin:/usr/bin:/bin"),
+        ("HOME", "/tmp"),
+        ("LANG", "C.UTF-8"),
+    ]
+    .iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -47,5 +196,63 @@ pub struct Config {
     pub judge_dir: String,
     pub cgroup: i32,
     pub rootfs: String,
+    #[serde(default)]
+    pub sandbox: SandboxKind,
+    #[serde(default = "default_newbie_sandbox")]
+    pub newbie_sandbox: String,
+    // 沙盒内程序运行时使用的用户与用户组
+    #[serde(default = "default_sandbox_id")]
+    pub sandbox_uid: u32,
+    #[serde(default = "default_sandbox_id")]
+    pub sandbox_gid: u32,
+    // 留给 River 自身的 CPU，评测程序不会运行在这些 CPU 上
+    #[serde(default)]
+    pub reserved_cpus: Vec<usize>,
+    // 每个物理核心只运行一个评测程序，不使用 SMT（超线程）的兄弟线程
+    #[serde(default)]
+    pub exclude_smt: bool,
+    // 每次运行绑定的 CPU 数量
+    #[serde(default = "default_cpus_per_run")]
+    pub cpus_per_run: usize,
+    // 沙盒内程序的基础环境变量，不会继承 River 自身的环境变量
+    #[serde(default = "default_env")]
+    pub env: BTreeMap<String, String>,
+    // 从源代码编译的 spj 按哈希缓存在此目录中，在不同的测试点与提交之间复用
+    #[serde(default)]
+    pub checker_cache: Option<String>,
+    // 编译信息与 spj 输出最多返回的字节数，超出的部分会被截断
+    #[serde(default = "default_message_limit")]
+    pub message_limit: usize,
     pub languages: HashMap<String, LanguageConf>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let command: Command = serde_yaml::from_str(r#""  java -cp 'a b' Main""#).unwrap();
+        assert_eq!(command.args(), ["java", "-cp", "a b", "Main"]);
+        assert_eq!(command.to_string(), "java -cp 'a b' Main");
+
+        let command: Command = serde_yaml::from_str("[./a.out, x  y]").unwrap();
+        assert_eq!(command.args(), ["./a.out", "x  y"]);
+
+        assert!(serde_yaml::from_str::<Command>(r#""gcc 'main.c""#).is_err());
+        assert!(serde_yaml::from_str::<Command>(r#""  ""#).is_err());
+        assert!(serde_yaml::from_str::<Command>("[]").is_err());
+        assert!(serde_yaml::from_str::<Command>(r#"["", a]"#).is_err());
+    }
+
+    #[test]
+    fn test_template() {
+        let config: Config = serde_yaml::from_str(include_str!("../config.template.yaml")).unwrap();
+        let java = &config.languages["Java"];
+        assert_eq!(java.run_cmd.args()[0], "/usr/bin/java");
+        assert!(java.run_env.contains_key("JAVA_TOOL_OPTIONS"));
+        assert!(java.seccomp);
+        assert!(!config.languages["Haskell"].seccomp);
+        assert_eq!(config.env, default_env());
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::mem;
+
+use crate::error::{Error, Result};
+
+static CPU_ROOT: &str = "/sys/devices/system/cpu";
+
+/// 当前进程可以使用的 CPU，容器或 taskset 限制之外的 CPU 不会出现在其中
+pub fn available() -> Vec<usize> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, mem::size_of_val(&set), &mut set) } < 0 {
+        return vec![];
+    }
+    (0..libc::CPU_SETSIZE as usize)
+        .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) })
+        .collect()
+}
+
+/// 与 cpu 共享同一个物理核心的逻辑 CPU（包括它自己），读取失败时认为没有 SMT
+pub fn siblings(cpu: usize) -> Vec<usize> {
+    let path = format!("{}/cpu{}/topology/thread_siblings_list", CPU_ROOT, cpu);
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|text| parse_list(&text))
+        .unwrap_or_else(|| vec![cpu])
+}
+
+/// 解析内核的 CPU 列表格式，例如 `0-3,8,10-11`
+pub fn parse_list(text: &str) -> Option<Vec<usize>> {
+    let mut cpus = vec![];
+    for part in text.trim().split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end): (usize, usize) = (start.parse().ok()?, end.parse().ok()?);
+                if start > end {
+                    return None;
+                }
+                cpus.extend(start..=end);
+            }
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// 将 CPU 分为若干组，每次运行独占其中一组
+///
+/// reserved 中的 CPU 留给 River 自身；exclude_smt 为 true 时每个物理核心只使用一个逻辑 CPU，
+/// 并且不使用与 reserved 共享物理核心的 CPU。不足 per_run 个的剩余 CPU 不会被使用。
+pub fn allocate<F>(
+    available: &[usize],
+    siblings: F,
+    reserved: &[usize],
+    exclude_smt: bool,
+    per_run: usize,
+) -> Vec<Vec<usize>>
+where
+    F: Fn(usize) -> Vec<usize>,
+{
+    let reserved: BTreeSet<usize> = reserved.iter().copied().collect();
+    let available: BTreeSet<usize> = available.iter().copied().collect();
+    let cpus: Vec<usize> = available
+        .iter()
+        .copied()
+        .filter(|cpu| !reserved.contains(cpu))
+        .filter(|cpu| {
+            !exclude_smt
+                || siblings(*cpu).iter().all(|sibling| {
+                    // 兄弟线程被保留，或者已经使用了同一核心中编号更小的线程
+                    sibling == cpu
+                        || !(reserved.contains(sibling)
+                            || sibling < cpu && available.contains(sibling))
+                })
+        })
+        .collect();
+    cpus.chunks_exact(per_run.max(1))
+        .map(|chunk| chunk.to_vec())
+        .collect()
+}
+
+/// 生成 sched_setaffinity 所需的 CPU 集合，超出 CPU_SETSIZE 的编号会被忽略
+pub fn cpu_set(cpus: &[usize]) -> libc::cpu_set_t {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for cpu in cpus {
+        if *cpu < libc::CPU_SETSIZE as usize {
+            unsafe { libc::CPU_SET(*cpu, &mut set) };
+        }
+    }
+    set
+}
+
+/// 将线程或进程绑定到给定的 CPU 上，pid 为 0 时表示调用者自身，只调用系统调用
+pub fn set_affinity(pid: libc::pid_t, set: &libc::cpu_set_t) -> bool {
+    unsafe { libc::sched_setaffinity(pid, mem::size_of_val(set), set) == 0 }
+}
+
+/// 将当前进程的所有线程绑定到给定的 CPU 上，之后创建的线程会继承此设置
+pub fn pin_process(cpus: &[usize]) -> Result<()> {
+    let set = cpu_set(cpus);
+    for entry in try_io!(fs::read_dir("/proc/self/task")).flatten() {
+        if let Ok(tid) = entry.file_name().to_string_lossy().parse() {
+            // 线程可能已经退出
+            if !set_affinity(tid, &set) && entry.path().exists() {
+                return Err(Error::IOError(std::io::Error::last_os_error()));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 个物理核心，每个核心有 2 个线程：(0, 4)、(1, 5)、(2, 6)、(3, 7)
+    fn smt(cpu: usize) -> Vec<usize> {
+        vec![cpu % 4, cpu % 4 + 4]
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_list("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_list("5"), Some(vec![5]));
+        assert_eq!(parse_list(""), Some(vec![]));
+        assert_eq!(parse_list("3-1"), None);
+        assert_eq!(parse_list("a"), None);
+    }
+
+    #[test]
+    fn test_allocate() {
+        let cpus: Vec<usize> = (0..8).collect();
+        assert_eq!(allocate(&cpus, smt, &[], false, 1).len(), 8);
+        assert_eq!(
+            allocate(&cpus, smt, &[0], false, 2),
+            vec![vec![1, 2], vec![3, 4], vec![5, 6]]
+        );
+        assert_eq!(
+            allocate(&cpus, smt, &[], true, 1),
+            vec![vec![0], vec![1], vec![2], vec![3]]
+        );
+        // 与保留的 CPU 共享物理核心的 4 也不会被使用
+        assert_eq!(
+            allocate(&cpus, smt, &[0], true, 1),
+            vec![vec![1], vec![2], vec![3]]
+        );
+        // 兄弟线程不可用时使用另一个线程
+        assert_eq!(allocate(&[4, 5], smt, &[], true, 1), vec![vec![4], vec![5]]);
+    }
+}
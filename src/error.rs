@@ -7,8 +7,11 @@ use std::io;
 use std::result;
 
 use libc::strerror;
+use tonic::Status;
 
-#[allow(dead_code)]
+use crate::river::ErrorCode;
+
+#[allow(dead_code, clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Error {
     IOError(io::Error),
@@ -22,6 +25,12 @@ pub enum Error {
     PathToStringError(),
     StringSplitError(),
     StringToIntError(String),
+    NotCompiled(),
+    SandboxNotFound(String),
+    TestDataNotFound(String),
+    InvalidRequest(String),
+    ProblemConfigError(String),
+    SandboxError(String),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -32,7 +41,7 @@ macro_rules! try_io {
     ($expression:expr) => {
         match $expression {
             Ok(val) => val,
-            Err(e) => return Err(crate::error::Error::IOError(e)),
+            Err(e) => return Err($crate::error::Error::IOError(e)),
         }
     };
 }
@@ -44,11 +53,63 @@ impl fmt::Display for Error {
             Error::CustomError(ref e) => write!(f, "Internal Server Error: `{}`", e),
             Error::LanguageNotFound(ref e) => write!(f, "Language Not Fount: `{}`", e),
             Error::SystemError(ref e) => write!(f, "System Error: `{}`", e),
+            Error::NotCompiled() => write!(f, "Not Compiled"),
+            Error::SandboxNotFound(ref e) => write!(f, "Sandbox Not Found: `{}`", e),
+            Error::TestDataNotFound(ref e) => write!(f, "Test Data Not Found: `{}`", e),
+            Error::InvalidRequest(ref e) => write!(f, "Invalid Request: `{}`", e),
+            Error::ProblemConfigError(ref e) => write!(f, "Problem Config Error: `{}`", e),
+            Error::SandboxError(ref e) => write!(f, "Sandbox Error: `{}`", e),
             _ => write!(f, "{:?}", self),
         }
     }
 }
 
+impl std::error::Error for Error {}
+
+impl Error {
+    /// 错误对应的结构化错误码，客户端可据此区分错误原因而无需解析文本
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            Error::LanguageNotFound(_) => ErrorCode::LanguageNotFound,
+            Error::NotCompiled() => ErrorCode::NotCompiled,
+            Error::SandboxNotFound(_) => ErrorCode::SandboxNotFound,
+            // 这些错误只会在解析沙盒的运行结果时出现
+            Error::SandboxError(_) | Error::StringSplitError() | Error::StringToIntError(_) => {
+                ErrorCode::SandboxFailure
+            }
+            Error::TestDataNotFound(_) => ErrorCode::TestDataNotFound,
+            Error::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            Error::ProblemConfigError(_) => ErrorCode::ProblemConfigError,
+            _ => ErrorCode::InternalError,
+        }
+    }
+
+    /// 是否为客户端请求本身的错误，此类错误无法通过重试解决
+    pub fn is_client_error(&self) -> bool {
+        matches!(
+            self.code(),
+            ErrorCode::LanguageNotFound | ErrorCode::InvalidRequest
+        )
+    }
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        let message = format!("{}", err);
+        match err.code() {
+            ErrorCode::LanguageNotFound | ErrorCode::InvalidRequest => {
+                Status::invalid_argument(message)
+            }
+            ErrorCode::NotCompiled | ErrorCode::ProblemConfigError => {
+                Status::failed_precondition(message)
+            }
+            ErrorCode::TestDataNotFound => Status::not_found(message),
+            ErrorCode::SandboxNotFound | ErrorCode::SandboxFailure => Status::unavailable(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
 pub fn errno_str(errno: Option<i32>) -> String {
     match errno {
         Some(no) => {
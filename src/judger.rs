@@ -1,21 +1,35 @@
+use std::collections::HashMap;
+use std::io::{ErrorKind, SeekFrom};
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use sha2::{Digest, Sha256};
 use tokio::fs;
-use tokio::fs::{remove_file, File};
-use tokio::io::AsyncReadExt;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 use crate::config::{
-    CONFIG, CPU_SEMAPHORE, RESULT_FILENAME, SPJ_ANSWER_FILENAME, SPJ_FILENAME, SPJ_INPUT_FILENAME,
-    SPJ_RESULT_FILENAME, SPJ_STDERR_FILENAME, SPJ_STDOUT_FILENAME, STDERR_FILENAME,
-    STDOUT_FILENAME,
+    Config, LanguageConf, MemoryMode, ADDRESS_SPACE_MLE_PERCENT, BUILD_DIRNAME,
+    CHECKER_CACHE_DIRNAME, CHECKER_DIRNAME, CONFIG, DETAIL_LIMIT, MAX_RUNS, OUTPUT_DIRNAME,
+    RESULT_FILENAME, RSS_MEMORY_CEILING, RUN_DIRNAME, SPJ_ANSWER_FILENAME, SPJ_FILENAME,
+    SPJ_INPUT_FILENAME, SPJ_MEMORY_LIMIT, SPJ_RESULT_FILENAME, SPJ_STDERR_FILENAME,
+    SPJ_STDOUT_FILENAME, SPJ_TIME_LIMIT, STDERR_FILENAME, STDERR_TAIL_LIMIT, STDOUT_FILENAME,
 };
 use crate::error::{Error, Result};
+use crate::problem;
+use crate::progress::Progress;
 use crate::result::{
-    accepted, compile_error, compile_success, memory_limit_exceeded, runtime_error, spj_result,
-    standard_result, time_limit_exceeded, wrong_answer,
+    accepted, checker_error, compile_error, compile_success, memory_limit_exceeded,
+    restricted_function, runtime_error, sanitize, signal_name, snippet, spj_result, standard_diff,
+    standard_result, time_limit_exceeded, with_timing, wrong_answer, wrong_answer_detail,
 };
-use crate::river::{JudgeResponse, JudgeResultEnum, JudgeType};
-use crate::sandbox::{ProcessExitStatus, Sandbox};
+use crate::river::{
+    GenerateCase, GenerateRequest, GenerateResponse, JudgeData, JudgeResponse, JudgeResultEnum,
+    JudgeType, RerunPolicy, ValidateCase, ValidateRequest, ValidateResponse,
+};
+use crate::sandbox::{backend, Limits, ProcessExitStatus, Sandbox, SandboxBackend, NO_STDIN};
+use crate::scheduler::SCHEDULER;
 
 fn path_to_string(path: &Path) -> Result<String> {
     if let Some(s) = path.to_str() {
@@ -24,214 +38,514 @@ fn path_to_string(path: &Path) -> Result<String> {
     Err(Error::PathToStringError())
 }
 
-pub async fn compile(language: &str, code: &str, path: &Path) -> Result<JudgeResponse> {
+fn language_conf(language: &str) -> Result<&'static LanguageConf> {
+    match CONFIG.languages.get(language) {
+        Some(val) => Ok(val),
+        None => Err(Error::LanguageNotFound(String::from(language))),
+    }
+}
+
+pub async fn compile(
+    language: &str,
+    code: &str,
+    path: &Path,
+    progress: &Progress,
+) -> Result<JudgeResponse> {
     info!("compile: language = `{}`", language);
-    let lang = match CONFIG.languages.get(language) {
-        Some(val) => val,
-        None => return Err(Error::LanguageNotFound(String::from(language))),
-    };
-    try_io!(fs::write(path.join(&lang.code_file), &code).await);
+    let lang = language_conf(language)?;
+    compile_with(backend(), lang, code, path, CONFIG.message_limit, progress).await
+}
+
+async fn compile_with(
+    backend: Arc<dyn SandboxBackend>,
+    lang: &LanguageConf,
+    code: &str,
+    path: &Path,
+    message_limit: usize,
+    progress: &Progress,
+) -> Result<JudgeResponse> {
+    let status = build(backend, lang, code, path, progress, || {
+        progress.compile_started()
+    })
+    .await?;
+    if status.exit_code != 0 || status.signal != 0 {
+        let errmsg = merged_output(path, message_limit).await?;
+        return Ok(with_timing(
+            compile_error(status.time_used, status.memory_used, &errmsg),
+            status.real_time_used,
+            1,
+        ));
+    }
+    Ok(with_timing(
+        compile_success(status.time_used, status.memory_used),
+        status.real_time_used,
+        1,
+    ))
+}
 
-    let semaphore = CPU_SEMAPHORE.clone();
-    let permit = semaphore.acquire().await;
+// 在沙盒中编译代码，编译产物保存在 path/build 中，编译器的输出保存在 path 中
+async fn build<F: FnOnce()>(
+    backend: Arc<dyn SandboxBackend>,
+    lang: &LanguageConf,
+    code: &str,
+    path: &Path,
+    progress: &Progress,
+    started: F,
+) -> Result<ProcessExitStatus> {
+    // 编译产物保存在单独的目录中，每个测试点运行前复制到干净的运行目录，编译器的输出则不会被复制
+    let build = path.join(BUILD_DIRNAME);
+    fresh_dir(&build).await?;
+    try_io!(fs::write(build.join(&lang.code_file), &code).await);
+
+    let permit = SCHEDULER.acquire(progress).await;
 
     let mut sandbox = Sandbox::new(
-        &lang.compile_cmd,
-        path_to_string(&path)?,
-        String::from(&CONFIG.rootfs),
+        lang.compile_cmd.args().to_vec(),
+        path_to_string(&build)?,
         path_to_string(&path.join(RESULT_FILENAME))?,
-        String::from("/STDIN/"),
+        String::from(NO_STDIN),
         path_to_string(&path.join(STDOUT_FILENAME))?,
         path_to_string(&path.join(STDERR_FILENAME))?,
-        8000,
-        1024 * 1024 * 100,
-        50 * 1024 * 1024,
-        i32::from(CONFIG.cgroup),
-        0,
+        Limits::new(8000, 1024 * 1024 * 100, 50 * 1024 * 1024, 0),
     );
-    let status = sandbox.spawn().await?;
+    sandbox.env = lang.compile_env.clone();
+    sandbox.rootfs = lang.rootfs.clone();
+    sandbox.overlay = lang.overlay.clone();
+    sandbox.cpus = permit.cpus().to_vec();
+    started();
+    let status = sandbox.spawn(backend).await?;
     drop(permit);
     info!("status = {:?}", status);
+    Ok(status)
+}
 
-    if status.exit_code != 0 || status.signal != 0 {
-        // 合并 stdout 与 stderr 为 errmsg
-        // 因为不同的语言、不同的编译器，错误信息输出到了不同的地方
-        let outmsg = read_file_2048(path.join(STDOUT_FILENAME)).await?;
-        let errmsg = read_file_2048(path.join(STDERR_FILENAME)).await?;
-        let errmsg = if outmsg == "" {
-            errmsg
-        } else if errmsg == "" {
-            outmsg
-        } else {
-            format!("{}\n{}", outmsg, errmsg)
-        };
-        return Ok(compile_error(status.time_used, status.memory_used, &errmsg));
+// 合并 stdout 与 stderr 为 errmsg
+// 因为不同的语言、不同的编译器（以及校验器），错误信息输出到了不同的地方
+async fn merged_output(path: &Path, limit: usize) -> Result<String> {
+    let outmsg = read_message(path.join(STDOUT_FILENAME), limit).await?;
+    let errmsg = read_message(path.join(STDERR_FILENAME), limit).await?;
+    Ok(if outmsg.is_empty() {
+        errmsg
+    } else if errmsg.is_empty() {
+        outmsg
+    } else {
+        format!("{}\n{}", outmsg, errmsg)
+    })
+}
+
+// 评测时依赖的外部环境，测试中使用 MockBackend 与临时目录代替
+struct Context<'a> {
+    backend: Arc<dyn SandboxBackend>,
+    data_dir: &'a Path,
+    // 编译后的 spj 的缓存目录
+    checker_cache: &'a Path,
+    // 编译信息与 spj 输出的最大字节数
+    message_limit: usize,
+    languages: &'a HashMap<String, LanguageConf>,
+}
+
+impl<'a> Context<'a> {
+    fn new(
+        config: &'a Config,
+        backend: Arc<dyn SandboxBackend>,
+        data_dir: &'a Path,
+        checker_cache: &'a Path,
+    ) -> Self {
+        Context {
+            backend,
+            data_dir,
+            checker_cache,
+            message_limit: config.message_limit,
+            languages: &config.languages,
+        }
+    }
+}
+
+fn checker_cache() -> PathBuf {
+    match &CONFIG.checker_cache {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&CONFIG.judge_dir).join(CHECKER_CACHE_DIRNAME),
     }
-    Ok(compile_success(status.time_used, status.memory_used))
 }
 
 pub async fn judge(
     language: &str,
-    in_file: &str,
-    out_file: &str,
-    spj_file: &str,
-    time_limit: i32,
-    memory_limit: i32,
-    judge_type: i32,
+    data: &JudgeData,
     path: &Path,
+    progress: &Progress,
 ) -> Result<JudgeResponse> {
+    let lang = language_conf(language)?;
+    let checker_cache = checker_cache();
+    let ctx = Context::new(
+        &CONFIG,
+        backend(),
+        Path::new(&CONFIG.data_dir),
+        &checker_cache,
+    );
+    judge_with(&ctx, language, lang, data, path, progress).await
+}
+
+async fn judge_with(
+    ctx: &Context<'_>,
+    language: &str,
+    lang: &LanguageConf,
+    data: &JudgeData,
+    path: &Path,
+    progress: &Progress,
+) -> Result<JudgeResponse> {
+    let data_dir = ctx.data_dir;
+    let in_file = &data.in_file;
+    let out_file = &data.out_file;
+    let time_limit = data.time_limit;
+    let memory_limit = data.memory_limit;
+    let judge_type = data.judge_type;
     info!("judge: language = `{}`, in_file = `{}`, out_file = `{}`, time_limit = `{}`,  memory_limit = `{}`, judge_type = `{}`", language, in_file, out_file, time_limit, memory_limit, judge_type);
-    let data_dir = Path::new(&CONFIG.data_dir);
 
-    let lang = match CONFIG.languages.get(language) {
-        Some(val) => val,
-        None => return Err(Error::LanguageNotFound(String::from(language))),
-    };
-    // 信号量控制并发
-    let semaphore = CPU_SEMAPHORE.clone();
-    let permit = semaphore.acquire().await;
+    for file in &[in_file, out_file] {
+        if !data_dir.join(file).is_file() {
+            return Err(Error::TestDataNotFound(String::from(*file)));
+        }
+    }
+    if matches!(&data.rerun, Some(rerun) if rerun.runs < 0) {
+        return Err(Error::InvalidRequest(String::from(
+            "field rerun.runs must not be negative",
+        )));
+    }
+    if data.stack_limit < -1 {
+        return Err(Error::InvalidRequest(String::from(
+            "field stack_limit must not be less than -1",
+        )));
+    }
+    // 每个测试点都在干净的目录中运行，上一个测试点留下的文件不会影响本次运行
+    let run = path.join(RUN_DIRNAME);
+    fresh_dir(&run).await?;
+    copy_dir(&path.join(BUILD_DIRNAME), &run).await?;
+    let output = path.join(OUTPUT_DIRNAME);
+    fresh_dir(&output).await?;
+
+    // 调度器控制并发
+    let permit = SCHEDULER.acquire(progress).await;
 
-    try_io!(remove_file(&path.join(RESULT_FILENAME)).await);
-    try_io!(remove_file(&path.join(STDOUT_FILENAME)).await);
-    try_io!(remove_file(&path.join(STDERR_FILENAME)).await);
     let mut sandbox = Sandbox::new(
-        &lang.run_cmd,
-        path_to_string(&path)?,
-        String::from(&CONFIG.rootfs),
-        path_to_string(&path.join(RESULT_FILENAME))?,
-        path_to_string(data_dir.join(&in_file).as_path())?,
-        path_to_string(&path.join(STDOUT_FILENAME))?,
-        path_to_string(&path.join(STDERR_FILENAME))?,
-        time_limit,
-        if language == "Java"
-            || language == "Go"
-            || language == "JavaScript"
-            || language == "TypeScript"
-            || language == "CSharp"
-        {
-            1024 * 1024
-        } else {
-            memory_limit
-        },
-        50 * 1024 * 1024,
-        i32::from(CONFIG.cgroup),
-        32,
+        lang.run_cmd.args().to_vec(),
+        path_to_string(&run)?,
+        String::new(),
+        path_to_string(data_dir.join(in_file).as_path())?,
+        String::new(),
+        String::new(),
+        Limits::new(
+            time_limit,
+            // 按 RSS 判定时只施加一个宽松的上限，由 verdict 比较峰值 RSS 与内存限制
+            match lang.memory_mode {
+                MemoryMode::Rss => memory_limit.max(RSS_MEMORY_CEILING),
+                _ => memory_limit,
+            },
+            50 * 1024 * 1024,
+            32,
+        ),
     );
-    let status = sandbox.spawn().await?;
+    sandbox.limits.memory_mode = lang.memory_mode;
+    sandbox.limits.stack = stack_limit(data);
+    sandbox.env = lang.run_env.clone();
+    sandbox.rootfs = lang.rootfs.clone();
+    sandbox.overlay = lang.overlay.clone();
+    sandbox.limits.real_time = data.real_time_limit;
+    if lang.seccomp {
+        sandbox.seccomp = Some(lang.syscalls.clone());
+    }
+    sandbox.loopback = data.localhost;
+    sandbox.cpus = permit.cpus().to_vec();
+    progress.case_started();
+    // 时间略微超出限制时重新运行，减少机器负载带来的误差
+    // 每次运行的输出保存在 output/<序号> 中，最终按被选中的那一次运行的状态与输出判定
+    let mut runs = vec![];
+    let mut times = vec![];
+    loop {
+        let dir = output.join(runs.len().to_string());
+        try_io!(fs::create_dir(&dir).await);
+        sandbox.result = path_to_string(&dir.join(RESULT_FILENAME))?;
+        sandbox.stdout = path_to_string(&dir.join(STDOUT_FILENAME))?;
+        sandbox.stderr = path_to_string(&dir.join(STDERR_FILENAME))?;
+        if !runs.is_empty() {
+            // 重新准备运行目录，上一次运行留下的文件不会影响本次运行
+            fresh_dir(&run).await?;
+            copy_dir(&path.join(BUILD_DIRNAME), &run).await?;
+        }
+        let status = sandbox.spawn(ctx.backend.clone()).await?;
+        times.push(status.time_used);
+        runs.push((status, dir));
+        if !should_rerun(data, &times) {
+            break;
+        }
+    }
     drop(permit);
 
-    if status.time_used > time_limit.into() {
-        // TLE
-        return Ok(time_limit_exceeded(status.time_used, status.memory_used));
-    } else if status.memory_used > memory_limit.into() {
-        // MLE
-        return Ok(memory_limit_exceeded(status.time_used, status.memory_used));
-    } else if status.signal != 0 {
-        // RE
-        return Ok(runtime_error(
+    let (status, output) = runs.swap_remove(rerun_index(data, &times));
+    let real_time_used = status.real_time_used;
+    let res = verdict(ctx, data, path, &output, &sandbox.limits, status, progress).await?;
+    Ok(with_timing(res, real_time_used, times.len()))
+}
+
+// 栈空间限制（KiB），-1 表示与内存限制相同
+fn stack_limit(data: &JudgeData) -> i32 {
+    match data.stack_limit {
+        -1 => data.memory_limit,
+        val if val > 0 => val,
+        _ => 0,
+    }
+}
+
+fn should_rerun(data: &JudgeData, times: &[i64]) -> bool {
+    let rerun = match &data.rerun {
+        Some(val) => val,
+        None => return false,
+    };
+    if times.len() >= rerun.runs.min(MAX_RUNS) as usize {
+        return false;
+    }
+    let limit = i64::from(data.time_limit);
+    let margin = if rerun.margin > 0 {
+        i64::from(rerun.margin)
+    } else {
+        limit / 10
+    };
+    // 只有第一次运行略微超时才会重新运行，明显超时的程序不会因为重新运行而通过
+    if times[0] <= limit || times[0] > limit + margin {
+        return false;
+    }
+    if rerun.policy == RerunPolicy::Minimum as i32 {
+        // 已经有一次在限制内时结果不会再改变
+        times.iter().all(|time| *time > limit)
+    } else {
+        true
+    }
+}
+
+// 用于判定的那一次运行的序号：CPU 时间最短或位于中位数的一次
+fn rerun_index(data: &JudgeData, times: &[i64]) -> usize {
+    let mut indexes: Vec<usize> = (0..times.len()).collect();
+    indexes.sort_by_key(|&i| times[i]);
+    match &data.rerun {
+        Some(rerun) if rerun.policy == RerunPolicy::Median as i32 => {
+            indexes[(indexes.len() - 1) / 2]
+        }
+        _ => indexes[0],
+    }
+}
+
+// 根据程序的运行状态与 output 中的输出给出评测结果
+async fn verdict(
+    ctx: &Context<'_>,
+    data: &JudgeData,
+    path: &Path,
+    output: &Path,
+    limits: &Limits,
+    status: ProcessExitStatus,
+    progress: &Progress,
+) -> Result<JudgeResponse> {
+    let time_limit = data.time_limit;
+    let memory_limit = data.memory_limit;
+    let judge_type = data.judge_type;
+    if let Some(syscall) = &status.syscall {
+        // 调用了被禁止的系统调用
+        return Ok(restricted_function(
             status.time_used,
             status.memory_used,
-            &format!("Program was interrupted by signal: `{}`", status.signal),
+            syscall,
         ));
-    } else if status.exit_code != 0 {
+    } else if status.time_used > time_limit.into()
+        || status.real_time_used >= limits.real_time_limit()
+    {
+        // TLE，超出墙上时间通常是因为程序在 sleep 或等待输入
+        return Ok(time_limit_exceeded(status.time_used, status.memory_used));
+    } else if status.oom_killed
+        || status.memory_used > memory_limit.into()
+        || allocation_failed(limits, memory_limit, &status)
+    {
+        // MLE，被 OOM killer 杀死的程序与内存分配失败的程序也属于此类，而不是 RE
+        return Ok(memory_limit_exceeded(status.time_used, status.memory_used));
+    } else if status.signal != 0 || status.exit_code != 0 {
         // RE
         // 就算是用户自己返回的非零，也算 RE
-        return Ok(runtime_error(
-            status.time_used,
-            status.memory_used,
-            &format!("Exceptional program return code: `{}`", status.exit_code),
-        ));
+        let reason = if status.signal != 0 {
+            format!(
+                "Program was interrupted by signal: `{}` ({})",
+                signal_name(status.signal),
+                status.signal
+            )
+        } else {
+            format!("Exceptional program return code: `{}`", status.exit_code)
+        };
+        // 附带 stderr 的末尾部分，其中通常是异常栈或 panic 信息
+        let stderr =
+            sanitize(&read_file_tail(output.join(STDERR_FILENAME), STDERR_TAIL_LIMIT).await?);
+        let errmsg = if stderr.trim().is_empty() {
+            reason
+        } else {
+            format!("{}\n{}", reason, stderr)
+        };
+        return Ok(runtime_error(status.time_used, status.memory_used, &errmsg));
     } else if judge_type == JudgeType::Standard as i32 {
         // 答案对比
-        let out = try_io!(fs::read(path.join(STDOUT_FILENAME)).await);
-        let ans = try_io!(fs::read(data_dir.join(&out_file)).await);
+        let mut out = vec![];
+        let mut stdout = open_output(&output.join(STDOUT_FILENAME)).await?;
+        try_io!(stdout.read_to_end(&mut out).await);
+        let ans = try_io!(fs::read(ctx.data_dir.join(&data.out_file)).await);
         let res = standard_result(&out, &ans)?;
         return if res == JudgeResultEnum::Accepted {
             Ok(accepted(status.time_used, status.memory_used))
+        } else if data.detail {
+            let limit = if data.detail_limit > 0 {
+                data.detail_limit as usize
+            } else {
+                DETAIL_LIMIT
+            };
+            let difference = standard_diff(&out, &ans, limit).unwrap_or_default();
+            let errmsg = read_file_head(output.join(STDERR_FILENAME), limit).await?;
+            Ok(wrong_answer_detail(
+                status.time_used,
+                status.memory_used,
+                difference,
+                &snippet(&out, limit),
+                &errmsg,
+            ))
         } else {
             Ok(wrong_answer(status.time_used, status.memory_used))
         };
     } else if judge_type == JudgeType::Special as i32 {
         // Special Judge
-        return special_judge(in_file, out_file, spj_file, path, data_dir, status).await;
+        return special_judge(ctx, data, path, output, status, progress).await;
     }
 
-    Err(Error::SystemError(String::from(format!("Unknown Error!"))))
+    Err(Error::SystemError(String::from("Unknown Error!")))
+}
+
+// 按地址空间限制时，超出限制表现为内存分配失败：程序异常退出（例如 bad_alloc 导致的 SIGABRT、
+// 使用分配失败返回的空指针导致的 SIGSEGV），而内存峰值接近但不会超出限制
+fn allocation_failed(limits: &Limits, memory_limit: i32, status: &ProcessExitStatus) -> bool {
+    limits.memory_mode == MemoryMode::AddressSpace
+        && (status.signal != 0 || status.exit_code != 0)
+        && status.memory_used * 100 >= i64::from(memory_limit) * ADDRESS_SPACE_MLE_PERCENT
 }
 
 async fn special_judge(
-    in_file: &str,
-    out_file: &str,
-    spj_file: &str,
+    ctx: &Context<'_>,
+    data: &JudgeData,
     path: &Path,
-    data_dir: &Path,
+    output: &Path,
     status: ProcessExitStatus,
+    progress: &Progress,
 ) -> Result<JudgeResponse> {
-    if spj_file == "" {
-        return Err(Error::SystemError(format!("field spj_file is required!")));
+    let data_dir = ctx.data_dir;
+    let (in_file, out_file, spj_file) = (&data.in_file, &data.out_file, &data.spj_file);
+    if spj_file.is_empty() {
+        return Err(Error::ProblemConfigError(String::from(
+            "field spj_file is required!",
+        )));
     }
-    let spj = data_dir.join(&spj_file);
+    let spj = data_dir.join(spj_file);
     if !spj.exists() {
-        return Err(Error::SystemError(format!(
+        return Err(Error::ProblemConfigError(format!(
             "Special Judge File `{}` Not Found!",
             spj_file
         )));
     }
-    // 将 spj 程序复制到沙盒内部
-    try_io!(fs::copy(spj, path.join(SPJ_FILENAME)).await);
-
-    // TODO: 创建 input file 与 answer file 的 named pipe，穿透沙盒以文件形式传递给 spj 程序？
-    // 此方案不稳定因素较多，比如两个阻塞写入的线程、异常处理等。在没有明显性能问题前先不实现此方案
-
-    // 将 input file 与 answer file 复制到沙盒内部，以供 spj 使用
-    try_io!(fs::copy(data_dir.join(&in_file), path.join(SPJ_INPUT_FILENAME)).await);
-    try_io!(fs::copy(data_dir.join(&out_file), path.join(SPJ_ANSWER_FILENAME)).await);
+    // spj 在独立的目录中运行，选手程序无法预先放置或篡改 spj 读取的文件
+    let path = &path.join(CHECKER_DIRNAME);
+    fresh_dir(path).await?;
+    // 将 spj 程序复制到沙盒内部，从源代码编译的 spj 使用其语言的运行命令
+    let lang = if data.spj_language.is_empty() {
+        try_io!(fs::copy(spj, path.join(SPJ_FILENAME)).await);
+        None
+    } else {
+        let (lang, cached) = compile_checker(ctx, data, progress).await?;
+        copy_dir(&cached, path).await?;
+        Some(lang)
+    };
 
     // Program must be run with the following arguments: <input-file> <output-file> <answer-file>
-    let spj_cmd = format!(
-        "{} {} {} {}",
-        SPJ_FILENAME, SPJ_INPUT_FILENAME, STDOUT_FILENAME, SPJ_ANSWER_FILENAME
+    let mut spj_cmd = match lang {
+        Some(lang) => lang.run_cmd.args().to_vec(),
+        None => vec![String::from(SPJ_FILENAME)],
+    };
+    spj_cmd.extend(
+        [SPJ_INPUT_FILENAME, STDOUT_FILENAME, SPJ_ANSWER_FILENAME]
+            .iter()
+            .map(|arg| arg.to_string()),
     );
 
-    let semaphore = CPU_SEMAPHORE.clone();
-    let permit = semaphore.acquire().await;
+    // 选手的输出通过已经打开并检查过的文件描述符交给沙盒，不会跟随其中的符号链接
+    let stdout = open_output(&output.join(STDOUT_FILENAME)).await?;
+
+    let permit = SCHEDULER.acquire(progress).await;
 
     let mut sandbox = Sandbox::new(
-        &spj_cmd,
-        path_to_string(&path)?,
-        String::from(&CONFIG.rootfs),
-        path_to_string(&path.join(SPJ_RESULT_FILENAME))?,
-        String::from("/STDIN/"),
-        path_to_string(&path.join(SPJ_STDOUT_FILENAME))?,
-        path_to_string(&path.join(SPJ_STDERR_FILENAME))?,
-        5000,
-        1024 * 1024,
-        50 * 1024 * 1024,
-        i32::from(CONFIG.cgroup),
-        8,
+        spj_cmd,
+        path_to_string(path)?,
+        path_to_string(&output.join(SPJ_RESULT_FILENAME))?,
+        String::from(NO_STDIN),
+        path_to_string(&output.join(SPJ_STDOUT_FILENAME))?,
+        path_to_string(&output.join(SPJ_STDERR_FILENAME))?,
+        Limits::new(
+            positive_or(data.spj_time_limit, SPJ_TIME_LIMIT),
+            positive_or(data.spj_memory_limit, SPJ_MEMORY_LIMIT),
+            50 * 1024 * 1024,
+            8,
+        ),
     );
-    let spj_status = sandbox.spawn().await?;
+    sandbox.cpus = permit.cpus().to_vec();
+    if let Some(lang) = lang {
+        sandbox.env = lang.run_env.clone();
+        sandbox.rootfs = lang.rootfs.clone();
+        sandbox.overlay = lang.overlay.clone();
+    }
+    // input file、选手的输出与 answer file 只读挂载到沙盒内部，不支持时由沙盒实现复制
+    sandbox.binds = vec![
+        (
+            path_to_string(&data_dir.join(in_file))?,
+            String::from(SPJ_INPUT_FILENAME),
+        ),
+        (
+            format!("/proc/self/fd/{}", stdout.as_raw_fd()),
+            String::from(STDOUT_FILENAME),
+        ),
+        (
+            path_to_string(&data_dir.join(out_file))?,
+            String::from(SPJ_ANSWER_FILENAME),
+        ),
+    ];
+    progress.checker_running();
+    let spj_status = sandbox.spawn(ctx.backend.clone()).await?;
     drop(permit);
+    drop(stdout);
 
     // 读取 spj 程序的输出，无论结果 ac 与否，都要将其返回
-    let outmsg = read_file_2048(path.join(SPJ_STDOUT_FILENAME)).await?;
-    let errmsg = read_file_2048(path.join(SPJ_STDERR_FILENAME)).await?;
+    let outmsg = read_message(output.join(SPJ_STDOUT_FILENAME), ctx.message_limit).await?;
+    let errmsg = read_message(output.join(SPJ_STDERR_FILENAME), ctx.message_limit).await?;
     // spj 程序的返回值（code）代表了结果，0 ac，1 wa
-    return if spj_status.exit_code == 0 && spj_status.signal == 0 {
-        Ok(spj_result(
+    if let Some(reason) = checker_failure(&spj_status, &sandbox.limits) {
+        // spj 自身出错时选手程序的结果未知
+        Ok(checker_error(
             status.time_used,
             status.memory_used,
-            JudgeResultEnum::Accepted,
-            &outmsg,
-            &errmsg,
+            &format!(
+                "checker failed: {}, exit={}, signal={}, status={}, time={}ms, real_time={}ms, memory={}KiB",
+                reason,
+                spj_status.exit_code,
+                spj_status.signal,
+                spj_status.status,
+                spj_status.time_used,
+                spj_status.real_time_used,
+                spj_status.memory_used
+            ),
         ))
-    } else if spj_status.signal != 0 {
+    } else if spj_status.exit_code == 0 {
         Ok(spj_result(
             status.time_used,
             status.memory_used,
-            JudgeResultEnum::SystemError,
-            "",
-            &format!("spj checker run failed, signal={}, exit={}, status={}, time={}, memory={}", spj_status.signal, spj_status.exit_code, spj_status.status, spj_status.time_used, spj_status.memory_used),
+            JudgeResultEnum::Accepted,
+            &outmsg,
+            &errmsg,
         ))
     } else {
         Ok(spj_result(
@@ -241,21 +555,1412 @@ async fn special_judge(
             &outmsg,
             &errmsg,
         ))
+    }
+}
+
+pub async fn validate(request: &ValidateRequest, path: &Path) -> Result<ValidateResponse> {
+    info!(
+        "validate: pid = `{}`, language = `{}`",
+        request.pid, request.language
+    );
+    let lang = language_conf(&request.language)?;
+    let data_dir = problem::dir(request.pid);
+    let checker_cache = checker_cache();
+    let ctx = Context::new(&CONFIG, backend(), &data_dir, &checker_cache);
+    // 校验不是评测流的一部分，没有进度需要推送
+    validate_with(&ctx, lang, request, path, &Progress::discard()).await
+}
+
+// 编译校验器后依次以每个测试点的输入文件作为 stdin 运行
+async fn validate_with(
+    ctx: &Context<'_>,
+    lang: &LanguageConf,
+    request: &ValidateRequest,
+    path: &Path,
+    progress: &Progress,
+) -> Result<ValidateResponse> {
+    let cases = problem::cases(ctx.data_dir).await;
+    if cases.is_empty() {
+        return Err(Error::TestDataNotFound(format!("problem {}", request.pid)));
+    }
+    compile_tool(ctx, "Validator", lang, &request.code, path, progress).await?;
+
+    let output = path.join(OUTPUT_DIRNAME);
+    let mut response = ValidateResponse { cases: vec![] };
+    for case in cases {
+        let mut sandbox = prepare_run(
+            lang,
+            path,
+            &[],
+            path_to_string(&ctx.data_dir.join(&case.r#in))?,
+            path_to_string(&output.join(STDOUT_FILENAME))?,
+            tool_limits(request.time_limit, request.memory_limit),
+        )
+        .await?;
+        let status = spawn_tool(ctx, &mut sandbox, progress).await?;
+        let (passed, message) = match checker_failure(&status, &sandbox.limits) {
+            Some(reason) => (false, tool_failure("validator", &reason, &status)),
+            None => (
+                status.exit_code == 0,
+                merged_output(&output, ctx.message_limit).await?,
+            ),
+        };
+        response.cases.push(ValidateCase {
+            r#in: case.r#in,
+            passed,
+            message,
+        });
+    }
+    Ok(response)
+}
+
+pub async fn generate(request: &GenerateRequest, path: &Path) -> Result<GenerateResponse> {
+    info!(
+        "generate: pid = `{}`, cases = `{}`",
+        request.pid,
+        request.args.len()
+    );
+    let (generator, solution) = match (&request.generator, &request.solution) {
+        (Some(generator), Some(solution)) => (generator, solution),
+        _ => {
+            return Err(Error::InvalidRequest(String::from(
+                "fields generator and solution are required",
+            )))
+        }
     };
+    let generator = (language_conf(&generator.language)?, generator.code.as_str());
+    let solution = (language_conf(&solution.language)?, solution.code.as_str());
+    let data_dir = problem::dir(request.pid);
+    let checker_cache = checker_cache();
+    let ctx = Context::new(&CONFIG, backend(), &data_dir, &checker_cache);
+    generate_with(
+        &ctx,
+        generator,
+        solution,
+        request,
+        path,
+        &Progress::discard(),
+    )
+    .await
 }
 
-async fn read_file_2048(filename: PathBuf) -> Result<String> {
-    let mut buffer = [0; 2048];
-    let mut file = try_io!(File::open(filename).await);
-    try_io!(file.read(&mut buffer).await);
+// 生成器与标准程序分别在 path 下的独立目录中编译运行，生成的数据先保存在 path/data 中
+async fn generate_with(
+    ctx: &Context<'_>,
+    (generator, generator_code): (&LanguageConf, &str),
+    (solution, solution_code): (&LanguageConf, &str),
+    request: &GenerateRequest,
+    path: &Path,
+    progress: &Progress,
+) -> Result<GenerateResponse> {
+    if request.args.is_empty() {
+        return Err(Error::InvalidRequest(String::from(
+            "field args is required",
+        )));
+    }
+    let mut args = vec![];
+    for (i, line) in request.args.iter().enumerate() {
+        match shell_words::split(line) {
+            Ok(val) => args.push(val),
+            Err(e) => {
+                return Err(Error::InvalidRequest(format!(
+                    "invalid generator arguments #{}: {}",
+                    i + 1,
+                    e
+                )))
+            }
+        }
+    }
+    let generator_dir = path.join("generator");
+    let solution_dir = path.join("solution");
+    let staging = path.join("data");
+    fresh_dir(&staging).await?;
+    compile_tool(
+        ctx,
+        "Generator",
+        generator,
+        generator_code,
+        &generator_dir,
+        progress,
+    )
+    .await?;
+    compile_tool(
+        ctx,
+        "Solution",
+        solution,
+        solution_code,
+        &solution_dir,
+        progress,
+    )
+    .await?;
 
-    let mut offset = 0;
-    for i in 0..2047 {
-        offset = i;
-        if buffer[i] == 0 {
-            break;
+    let offset = if request.append {
+        problem::cases(ctx.data_dir).await.len()
+    } else {
+        0
+    };
+    let mut response = GenerateResponse { cases: vec![] };
+    for (i, args) in args.iter().enumerate() {
+        let (in_file, out_file) = problem::case_files(offset + i + 1);
+
+        let mut sandbox = prepare_run(
+            generator,
+            &generator_dir,
+            args,
+            String::from(NO_STDIN),
+            path_to_string(&staging.join(&in_file))?,
+            generate_limits(request),
+        )
+        .await?;
+        let status = spawn_tool(ctx, &mut sandbox, progress).await?;
+        if let Some(reason) = program_failure(ctx, "generator", &sandbox, &status).await? {
+            return Err(Error::ProblemConfigError(format!(
+                "{} (args #{}: `{}`)",
+                reason,
+                i + 1,
+                request.args[i]
+            )));
         }
+
+        let mut sandbox = prepare_run(
+            solution,
+            &solution_dir,
+            &[],
+            path_to_string(&staging.join(&in_file))?,
+            path_to_string(&staging.join(&out_file))?,
+            generate_limits(request),
+        )
+        .await?;
+        let status = spawn_tool(ctx, &mut sandbox, progress).await?;
+        if let Some(reason) = program_failure(ctx, "solution", &sandbox, &status).await? {
+            return Err(Error::ProblemConfigError(format!(
+                "{} (input: `{}`)",
+                reason, in_file
+            )));
+        }
+        response.cases.push(GenerateCase {
+            r#in: in_file,
+            out: out_file,
+            time_used: status.time_used,
+            memory_used: status.memory_used,
+        });
+    }
+
+    // 全部生成成功后才修改题目的测试数据
+    let mut files = vec![];
+    for case in &response.cases {
+        files.push(case.r#in.as_str());
+        files.push(case.out.as_str());
+    }
+    problem::replace_cases(ctx.data_dir, &staging, &files, request.append).await?;
+    Ok(response)
+}
+
+// 生成的数据可能较大，输出文件的大小限制比其他程序宽松
+fn generate_limits(request: &GenerateRequest) -> Limits {
+    let mut limits = tool_limits(request.time_limit, request.memory_limit);
+    limits.file_size = 1024 * 1024 * 1024;
+    limits
+}
+
+// 编译校验器、生成器等辅助程序，编译失败属于题目配置错误
+async fn compile_tool(
+    ctx: &Context<'_>,
+    name: &str,
+    lang: &LanguageConf,
+    code: &str,
+    path: &Path,
+    progress: &Progress,
+) -> Result<()> {
+    let status = build(ctx.backend.clone(), lang, code, path, progress, || {}).await?;
+    if status.exit_code != 0 || status.signal != 0 {
+        let errmsg = merged_output(path, ctx.message_limit).await?;
+        return Err(Error::ProblemConfigError(format!(
+            "{} Compile Error: {}",
+            name, errmsg
+        )));
+    }
+    Ok(())
+}
+
+// 辅助程序的资源限制，0 表示使用与 spj 相同的默认值
+fn tool_limits(time_limit: i32, memory_limit: i32) -> Limits {
+    Limits::new(
+        positive_or(time_limit, SPJ_TIME_LIMIT),
+        positive_or(memory_limit, SPJ_MEMORY_LIMIT),
+        50 * 1024 * 1024,
+        8,
+    )
+}
+
+// 在干净的 path/run 中准备运行 path/build 中编译好的程序，args 追加在运行命令之后，
+// stderr 与运行结果保存在 path/output 中
+async fn prepare_run(
+    lang: &LanguageConf,
+    path: &Path,
+    args: &[String],
+    stdin: String,
+    stdout: String,
+    limits: Limits,
+) -> Result<Sandbox> {
+    let run = path.join(RUN_DIRNAME);
+    fresh_dir(&run).await?;
+    copy_dir(&path.join(BUILD_DIRNAME), &run).await?;
+    let output = path.join(OUTPUT_DIRNAME);
+    fresh_dir(&output).await?;
+    let mut inner_args = lang.run_cmd.args().to_vec();
+    inner_args.extend_from_slice(args);
+    let mut sandbox = Sandbox::new(
+        inner_args,
+        path_to_string(&run)?,
+        path_to_string(&output.join(RESULT_FILENAME))?,
+        stdin,
+        stdout,
+        path_to_string(&output.join(STDERR_FILENAME))?,
+        limits,
+    );
+    sandbox.env = lang.run_env.clone();
+    sandbox.rootfs = lang.rootfs.clone();
+    sandbox.overlay = lang.overlay.clone();
+    Ok(sandbox)
+}
+
+async fn spawn_tool(
+    ctx: &Context<'_>,
+    sandbox: &mut Sandbox,
+    progress: &Progress,
+) -> Result<ProcessExitStatus> {
+    let permit = SCHEDULER.acquire(progress).await;
+    sandbox.cpus = permit.cpus().to_vec();
+    let status = sandbox.spawn(ctx.backend.clone()).await?;
+    drop(permit);
+    Ok(status)
+}
+
+fn tool_failure(name: &str, reason: &str, status: &ProcessExitStatus) -> String {
+    format!(
+        "{} failed: {}, time={}ms, real_time={}ms, memory={}KiB",
+        name, reason, status.time_used, status.real_time_used, status.memory_used
+    )
+}
+
+// 辅助程序超出资源限制或返回非零时的错误信息，其中包含程序的 stderr
+async fn program_failure(
+    ctx: &Context<'_>,
+    name: &str,
+    sandbox: &Sandbox,
+    status: &ProcessExitStatus,
+) -> Result<Option<String>> {
+    if let Some(reason) = checker_failure(status, &sandbox.limits) {
+        return Ok(Some(tool_failure(name, &reason, status)));
+    }
+    if status.exit_code == 0 {
+        return Ok(None);
+    }
+    let errmsg = read_message(PathBuf::from(&sandbox.stderr), ctx.message_limit).await?;
+    Ok(Some(format!(
+        "{} failed: exit={}\n{}",
+        name, status.exit_code, errmsg
+    )))
+}
+
+fn positive_or(val: i32, default: i32) -> i32 {
+    if val > 0 {
+        val
+    } else {
+        default
+    }
+}
+
+// spj 超出资源限制或被信号杀死时返回原因，此时它的返回值没有意义
+fn checker_failure(status: &ProcessExitStatus, limits: &Limits) -> Option<String> {
+    if status.time_used > limits.time.into() || status.real_time_used >= limits.real_time_limit() {
+        Some(String::from("time limit exceeded"))
+    } else if status.oom_killed || status.memory_used > limits.memory.into() {
+        Some(String::from("memory limit exceeded"))
+    } else if status.signal != 0 {
+        Some(format!(
+            "interrupted by signal `{}`",
+            signal_name(status.signal)
+        ))
+    } else {
+        None
+    }
+}
+
+// 编译 spj 的源代码，返回其语言与缓存的编译产物目录
+//
+// 缓存以源代码与编译配置的哈希命名，先在临时目录中编译，成功后再重命名，
+// 因此并发的评测不会读取到不完整的编译结果
+async fn compile_checker<'a>(
+    ctx: &Context<'a>,
+    data: &JudgeData,
+    progress: &Progress,
+) -> Result<(&'a LanguageConf, PathBuf)> {
+    let lang = match ctx.languages.get(&data.spj_language) {
+        Some(lang) => lang,
+        None => {
+            return Err(Error::ProblemConfigError(format!(
+                "Special Judge Language `{}` Not Found!",
+                data.spj_language
+            )))
+        }
+    };
+    let source = try_io!(fs::read(ctx.data_dir.join(&data.spj_file)).await);
+    let source = match String::from_utf8(source) {
+        Ok(source) => source,
+        Err(_) => {
+            return Err(Error::ProblemConfigError(format!(
+                "Special Judge File `{}` is not valid UTF-8!",
+                data.spj_file
+            )))
+        }
+    };
+    let cached = ctx.checker_cache.join(checker_hash(lang, &source));
+    if cached.is_dir() {
+        return Ok((lang, cached));
+    }
+
+    try_io!(fs::create_dir_all(ctx.checker_cache).await);
+    let tmp = try_io!(tempfile::Builder::new()
+        .prefix(".")
+        .tempdir_in(ctx.checker_cache));
+    let status = build(
+        ctx.backend.clone(),
+        lang,
+        &source,
+        tmp.path(),
+        progress,
+        || {},
+    )
+    .await?;
+    if status.exit_code != 0 || status.signal != 0 {
+        let errmsg = merged_output(tmp.path(), ctx.message_limit).await?;
+        return Err(Error::ProblemConfigError(format!(
+            "Special Judge File `{}` Compile Error: {}",
+            data.spj_file, errmsg
+        )));
+    }
+    if let Err(e) = fs::rename(tmp.path().join(BUILD_DIRNAME), &cached).await {
+        // 其他评测已经缓存了相同的 spj
+        if !cached.is_dir() {
+            return Err(Error::IOError(e));
+        }
+    }
+    Ok((lang, cached))
+}
+
+// 编译结果只取决于源代码与编译配置，包括编译所在的镜像与环境变量
+fn checker_hash(lang: &LanguageConf, source: &str) -> String {
+    let mut parts = vec![
+        lang.compile_cmd.to_string(),
+        lang.code_file.clone(),
+        lang.version.clone(),
+        lang.rootfs.clone().unwrap_or_default(),
+    ];
+    // 带上数量，各个列表的边界不会因为内容而混淆
+    parts.push(lang.overlay.len().to_string());
+    parts.extend(lang.overlay.iter().cloned());
+    parts.push(lang.compile_env.len().to_string());
+    parts.extend(
+        lang.compile_env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value)),
+    );
+    parts.push(String::from(source));
+    let mut hasher = Sha256::new();
+    for part in &parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// 删除目录中的所有内容后重新创建
+async fn fresh_dir(path: &Path) -> Result<()> {
+    match fs::remove_dir_all(path).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::IOError(e)),
+    }
+    try_io!(fs::create_dir_all(path).await);
+    Ok(())
+}
+
+// 递归复制目录中的文件，符号链接保持为符号链接
+async fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    let mut dirs = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = dirs.pop() {
+        let mut entries = try_io!(fs::read_dir(&from).await);
+        while let Some(entry) = try_io!(entries.next_entry().await) {
+            let target = to.join(entry.file_name());
+            let file_type = try_io!(entry.file_type().await);
+            if file_type.is_dir() {
+                try_io!(fs::create_dir(&target).await);
+                dirs.push((entry.path(), target));
+            } else if file_type.is_symlink() {
+                let link = try_io!(fs::read_link(entry.path()).await);
+                try_io!(fs::symlink(link, &target).await);
+            } else {
+                try_io!(fs::copy(entry.path(), &target).await);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 打开程序的输出文件，不跟随符号链接，并拒绝普通文件之外的任何文件
+// O_NONBLOCK 避免打开命名管道时阻塞，对普通文件的读取没有影响
+async fn open_output(filename: &Path) -> Result<File> {
+    let file = try_io!(
+        fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+            .open(filename)
+            .await
+    );
+    if !try_io!(file.metadata().await).is_file() {
+        return Err(Error::SystemError(format!(
+            "`{}` is not a regular file",
+            filename.display()
+        )));
+    }
+    Ok(file)
+}
+
+// 读取文件开头最多 limit 个字节
+async fn read_file_head(filename: PathBuf, limit: usize) -> Result<String> {
+    let file = open_output(&filename).await?;
+    let mut buffer = vec![];
+    try_io!(file.take(limit as u64).read_to_end(&mut buffer).await);
+    Ok(snippet(&buffer, limit))
+}
+
+// 读取文件末尾最多 limit 个字节，丢弃开头不完整的多字节字符
+async fn read_file_tail(filename: PathBuf, limit: usize) -> Result<String> {
+    let mut file = open_output(&filename).await?;
+    let len = try_io!(file.metadata().await).len();
+    let start = len.saturating_sub(limit as u64);
+    try_io!(file.seek(SeekFrom::Start(start)).await);
+    let mut buffer = vec![];
+    try_io!(file.read_to_end(&mut buffer).await);
+    let skip = if start > 0 {
+        // UTF-8 的后续字节均为 0b10xxxxxx
+        buffer
+            .iter()
+            .take(3)
+            .take_while(|ch| **ch & 0xC0 == 0x80)
+            .count()
+    } else {
+        0
+    };
+    Ok(String::from(String::from_utf8_lossy(&buffer[skip..])))
+}
+
+// 读取文件开头最多 limit 个字节作为返回给用户的消息
+//
+// 被截断的多字节字符会被丢弃，其他无效的 UTF-8 字节（例如二进制数据）替换为 U+FFFD，
+// 文件超出 limit 时在末尾附加截断标记与文件的实际大小
+async fn read_message(filename: PathBuf, limit: usize) -> Result<String> {
+    let file = try_io!(File::open(filename).await);
+    let len = try_io!(file.metadata().await).len();
+    let mut buffer = vec![];
+    try_io!(file.take(limit as u64).read_to_end(&mut buffer).await);
+    if len <= buffer.len() as u64 {
+        return Ok(String::from(String::from_utf8_lossy(&buffer)));
+    }
+    let end = complete_prefix(&buffer);
+    Ok(format!(
+        "{}\n[truncated, {} bytes in total]",
+        String::from_utf8_lossy(&buffer[..end]),
+        len
+    ))
+}
+
+// 去掉末尾不完整的 UTF-8 字符后的长度
+fn complete_prefix(buffer: &[u8]) -> usize {
+    for back in 1..=buffer.len().min(3) {
+        let start = buffer.len() - back;
+        // 跳过后续字节（0b10xxxxxx），找到最后一个字符的首字节
+        if buffer[start] & 0xC0 == 0x80 {
+            continue;
+        }
+        let width = match buffer[start] {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        return if width > back { start } else { buffer.len() };
+    }
+    buffer.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Command;
+    use crate::river::judge_response::State;
+    use crate::river::{ErrorCode, JudgeResult, Rerun};
+    use crate::sandbox::{MockBackend, MockRun};
+    use lazy_static::lazy_static;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    fn language() -> LanguageConf {
+        LanguageConf {
+            compile_cmd: Command::parse("gcc main.c -o main").unwrap(),
+            code_file: String::from("main.c"),
+            run_cmd: Command::parse("./main").unwrap(),
+            version: String::from("gcc"),
+            syscalls: vec![String::from("clone")],
+            seccomp: true,
+            memory_mode: MemoryMode::Cgroup,
+            compile_env: env(&[("HOME", "/build")]),
+            run_env: env(&[("MODE", "run")]),
+            rootfs: None,
+            overlay: vec![],
+        }
+    }
+
+    fn env(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn data(detail: bool) -> JudgeData {
+        JudgeData {
+            in_file: String::from("1.in"),
+            out_file: String::from("1.out"),
+            time_limit: 1000,
+            memory_limit: 65536,
+            judge_type: JudgeType::Standard as i32,
+            detail,
+            ..Default::default()
+        }
+    }
+
+    fn run(time_used: i64, memory_used: i64, exit_code: i64, signal: i64, stdout: &str) -> MockRun {
+        MockRun {
+            status: ProcessExitStatus {
+                time_used,
+                memory_used,
+                exit_code,
+                signal,
+                ..Default::default()
+            },
+            stdout: String::from(stdout),
+            stderr: String::from(""),
+            files: vec![],
+            stdout_link: None,
+        }
+    }
+
+    async fn judge_mock(runs: Vec<MockRun>, data: &JudgeData) -> (JudgeResult, Arc<MockBackend>) {
+        judge_mock_with(&language(), runs, data).await
+    }
+
+    async fn judge_mock_with(
+        lang: &LanguageConf,
+        runs: Vec<MockRun>,
+        data: &JudgeData,
+    ) -> (JudgeResult, Arc<MockBackend>) {
+        let cache = tempdir().unwrap();
+        let (res, backend) = judge_mock_in(lang, runs, data, cache.path()).await;
+        (res.unwrap(), backend)
+    }
+
+    // 在给定的 spj 缓存目录中评测，data_dir 中的 checker.c 为 spj 的源代码
+    async fn judge_mock_in(
+        lang: &LanguageConf,
+        runs: Vec<MockRun>,
+        data: &JudgeData,
+        checker_cache: &Path,
+    ) -> (Result<JudgeResult>, Arc<MockBackend>) {
+        let data_dir = tempdir().unwrap();
+        std::fs::write(data_dir.path().join("1.in"), "1 2\n").unwrap();
+        std::fs::write(data_dir.path().join("1.out"), "3\n").unwrap();
+        std::fs::write(data_dir.path().join("spj"), "checker").unwrap();
+        std::fs::write(data_dir.path().join("checker.c"), "int main() {}").unwrap();
+        let pwd = tempdir().unwrap();
+        std::fs::create_dir(pwd.path().join(BUILD_DIRNAME)).unwrap();
+        std::fs::write(pwd.path().join(BUILD_DIRNAME).join("main"), "binary").unwrap();
+        let backend = Arc::new(MockBackend::new(runs));
+        let ctx = context(backend.clone(), data_dir.path(), checker_cache);
+        let res = judge_with(&ctx, "C", lang, data, pwd.path(), &Progress::discard()).await;
+        let res = res.map(|res| match res.state {
+            Some(State::Result(res)) => res,
+            _ => panic!("unexpected response: {:?}", res),
+        });
+        (res, backend)
+    }
+
+    fn languages() -> HashMap<String, LanguageConf> {
+        let mut languages = HashMap::new();
+        languages.insert(String::from("C"), language());
+        languages
+    }
+
+    lazy_static! {
+        static ref LANGUAGES: HashMap<String, LanguageConf> = languages();
+    }
+
+    fn context<'a>(
+        backend: Arc<MockBackend>,
+        data_dir: &'a Path,
+        checker_cache: &'a Path,
+    ) -> Context<'a> {
+        Context {
+            backend,
+            data_dir,
+            checker_cache,
+            message_limit: 1024,
+            languages: &LANGUAGES,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compile() {
+        let pwd = tempdir().unwrap();
+        let backend = Arc::new(MockBackend::new(vec![run(100, 1024, 1, 0, "error")]));
+        let res = compile_with(
+            backend.clone(),
+            &language(),
+            "int main() {}",
+            pwd.path(),
+            1024,
+            &Progress::discard(),
+        )
+        .await
+        .unwrap();
+        match res.state {
+            Some(State::Result(res)) => {
+                assert_eq!(res.result, JudgeResultEnum::CompileError as i32);
+                assert_eq!(res.errmsg, "error");
+            }
+            _ => panic!("unexpected response: {:?}", res),
+        }
+        let build = pwd.path().join(BUILD_DIRNAME);
+        assert!(build.join("main.c").is_file());
+        // 编译器的输出不在编译目录中，不会被复制到运行目录
+        assert!(!build.join(STDERR_FILENAME).exists());
+        let spawned = backend.spawned();
+        assert_eq!(spawned.len(), 1);
+        assert_eq!(spawned[0].workdir, build.to_str().unwrap());
+        assert_eq!(spawned[0].inner_args, vec!["gcc", "main.c", "-o", "main"]);
+        assert_eq!(spawned[0].stdin, NO_STDIN);
+        // 编译时的环境变量覆盖基础环境中的同名变量
+        let base = env(&[("HOME", "/tmp"), ("PATH", "/bin")]);
+        assert_eq!(
+            spawned[0].environment(&base),
+            vec![
+                (String::from("HOME"), String::from("/build")),
+                (String::from("PATH"), String::from("/bin")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_accepted() {
+        let (res, backend) = judge_mock(vec![run(10, 1024, 0, 0, "3")], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::Accepted as i32);
+        assert_eq!(res.time_used, 10);
+        let spawned = backend.spawned();
+        assert_eq!(spawned[0].inner_args, vec!["./main"]);
+        assert_eq!(spawned[0].limits.time, 1000);
+        assert_eq!(spawned[0].limits.memory, 65536);
+        assert_eq!(spawned[0].seccomp, Some(vec![String::from("clone")]));
+        assert!(!spawned[0].loopback);
+        assert_eq!(spawned[0].env, env(&[("MODE", "run")]));
+        // 每次运行绑定调度器分配的一个 CPU
+        assert_eq!(spawned[0].cpus.len(), 1);
+
+        let mut localhost = data(false);
+        localhost.localhost = true;
+        let (_, backend) = judge_mock(vec![run(10, 1024, 0, 0, "3")], &localhost).await;
+        assert!(backend.spawned()[0].loopback);
+
+        // 关闭 seccomp 的语言运行时不过滤系统调用
+        let mut lang = language();
+        lang.seccomp = false;
+        let (_, backend) =
+            judge_mock_with(&lang, vec![run(10, 1024, 0, 0, "3")], &data(false)).await;
+        assert_eq!(backend.spawned()[0].seccomp, None);
+    }
+
+    #[tokio::test]
+    async fn test_restricted_function() {
+        let mut blocked = run(10, 1024, 0, 9, "");
+        blocked.status.syscall = Some(String::from("fork"));
+        let (res, _) = judge_mock(vec![blocked], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::RestrictedFunction as i32);
+        assert!(res.errmsg.contains("fork"));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_answer() {
+        let (res, _) = judge_mock(vec![run(10, 1024, 0, 0, "4\n")], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::WrongAnswer as i32);
+        assert!(res.difference.is_none());
+
+        let (res, _) = judge_mock(vec![run(10, 1024, 0, 0, "4\n")], &data(true)).await;
+        assert_eq!(res.result, JudgeResultEnum::WrongAnswer as i32);
+        let difference = res.difference.unwrap();
+        assert_eq!(difference.expected, "3");
+        assert_eq!(difference.actual, "4");
+        assert_eq!(res.outmsg, "4\n");
+    }
+
+    #[tokio::test]
+    async fn test_output_symlink() {
+        // 指向正确答案的符号链接不会被当作程序的输出
+        let dir = tempdir().unwrap();
+        let answer = dir.path().join("answer");
+        std::fs::write(&answer, "3\n").unwrap();
+        let mut linked = run(10, 1024, 0, 0, "");
+        linked.stdout_link = Some(String::from(answer.to_str().unwrap()));
+        let cache = tempdir().unwrap();
+        let (res, _) = judge_mock_in(&language(), vec![linked], &data(true), cache.path()).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limit_exceeded() {
+        let (res, _) = judge_mock(vec![run(1001, 1024, 0, 0, "3")], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::TimeLimitExceeded as i32);
+
+        let (res, _) = judge_mock(vec![run(10, 65537, 0, 0, "3")], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::MemoryLimitExceeded as i32);
+
+        // 被 OOM killer 杀死时不是 RE
+        let mut killed = run(10, 65536, 0, 9, "");
+        killed.status.oom_killed = true;
+        let (res, _) = judge_mock(vec![killed], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::MemoryLimitExceeded as i32);
+    }
+
+    #[tokio::test]
+    async fn test_memory_mode() {
+        let (_, backend) = judge_mock(vec![run(10, 1024, 0, 0, "3")], &data(false)).await;
+        let limits = &backend.spawned()[0].limits;
+        assert_eq!(limits.memory, 65536);
+        assert_eq!(limits.memory_mode, MemoryMode::Cgroup);
+
+        // 按 RSS 判定时沙盒只施加宽松的上限
+        let mut rss = language();
+        rss.memory_mode = MemoryMode::Rss;
+        let runs = vec![run(10, 70000, 0, 0, "3")];
+        let (res, backend) = judge_mock_with(&rss, runs, &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::MemoryLimitExceeded as i32);
+        let limits = &backend.spawned()[0].limits;
+        assert_eq!(limits.memory, RSS_MEMORY_CEILING);
+        assert_eq!(limits.memory_mode, MemoryMode::Rss);
+
+        // 按地址空间限制时，内存峰值接近限制的异常退出是内存分配失败
+        let mut address_space = language();
+        address_space.memory_mode = MemoryMode::AddressSpace;
+        let runs = vec![run(10, 60000, 0, 11, "")];
+        let (res, _) = judge_mock_with(&address_space, runs, &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::MemoryLimitExceeded as i32);
+        let runs = vec![run(10, 60000, 134, 0, "")];
+        let (res, _) = judge_mock_with(&address_space, runs, &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::MemoryLimitExceeded as i32);
+        let runs = vec![run(10, 1024, 0, 11, "")];
+        let (res, _) = judge_mock_with(&address_space, runs, &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::RuntimeError as i32);
+        let runs = vec![run(10, 60000, 0, 0, "3")];
+        let (res, _) = judge_mock_with(&address_space, runs, &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::Accepted as i32);
+        // 其他模式下超出限制的程序会被杀死，异常退出仍是 RE
+        let (res, _) = judge_mock(vec![run(10, 60000, 0, 11, "")], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::RuntimeError as i32);
+    }
+
+    #[tokio::test]
+    async fn test_real_time() {
+        let mut idle = run(10, 1024, 0, 9, "");
+        idle.status.real_time_used = 3000;
+        let (res, backend) = judge_mock(vec![idle], &data(false)).await;
+        // 默认的墙上时间限制为 1000 * 2 + 1000
+        assert_eq!(res.result, JudgeResultEnum::TimeLimitExceeded as i32);
+        assert_eq!(res.time_used, 10);
+        assert_eq!(res.real_time_used, 3000);
+        assert_eq!(backend.spawned()[0].limits.real_time_limit(), 3000);
+
+        let mut limited = data(false);
+        limited.real_time_limit = 5000;
+        let mut slow = run(10, 1024, 0, 0, "3");
+        slow.status.real_time_used = 3000;
+        let (res, backend) = judge_mock(vec![slow], &limited).await;
+        assert_eq!(res.result, JudgeResultEnum::Accepted as i32);
+        assert_eq!(res.real_time_used, 3000);
+        assert_eq!(backend.spawned()[0].limits.real_time_limit(), 5000);
+    }
+
+    #[tokio::test]
+    async fn test_rerun() {
+        let (res, _) = judge_mock(vec![run(1050, 1024, 0, 0, "3")], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::TimeLimitExceeded as i32);
+        assert_eq!(res.runs, 1);
+
+        let mut minimum = data(false);
+        minimum.rerun = Some(Rerun {
+            runs: 3,
+            margin: 0,
+            policy: RerunPolicy::Minimum as i32,
+        });
+        let runs = vec![run(1050, 1024, 0, 0, "3"), run(990, 1024, 0, 0, "3")];
+        let (res, backend) = judge_mock(runs, &minimum).await;
+        assert_eq!(res.result, JudgeResultEnum::Accepted as i32);
+        assert_eq!(res.time_used, 990);
+        assert_eq!(res.runs, 2);
+        assert_eq!(backend.spawned().len(), 2);
+
+        // 超出限制太多时不会重新运行
+        let (res, _) = judge_mock(vec![run(1200, 1024, 0, 0, "3")], &minimum).await;
+        assert_eq!(res.result, JudgeResultEnum::TimeLimitExceeded as i32);
+        assert_eq!(res.runs, 1);
+
+        let mut median = minimum.clone();
+        median.rerun.as_mut().unwrap().policy = RerunPolicy::Median as i32;
+        let runs = vec![
+            run(1050, 1024, 0, 0, "3"),
+            run(990, 1024, 0, 0, "3"),
+            run(1020, 1024, 0, 0, "3"),
+        ];
+        let (res, _) = judge_mock(runs, &median).await;
+        assert_eq!(res.result, JudgeResultEnum::TimeLimitExceeded as i32);
+        assert_eq!(res.time_used, 1020);
+        assert_eq!(res.runs, 3);
+
+        // 各次运行的结果不一致时，状态、内存、墙上时间与输出都来自时间被采用的那一次运行
+        let mut wrong = run(1050, 4096, 0, 0, "4");
+        wrong.status.real_time_used = 1100;
+        let mut correct = run(990, 1024, 0, 0, "3");
+        correct.status.real_time_used = 1000;
+        let (res, _) = judge_mock(vec![wrong, correct], &minimum).await;
+        assert_eq!(res.result, JudgeResultEnum::Accepted as i32);
+        assert_eq!(
+            (res.time_used, res.memory_used, res.real_time_used),
+            (990, 1024, 1000)
+        );
+        let runs = vec![run(1050, 1024, 0, 0, "3"), run(990, 2048, 1, 0, "")];
+        let (res, _) = judge_mock(runs, &minimum).await;
+        assert_eq!(res.result, JudgeResultEnum::RuntimeError as i32);
+        assert_eq!((res.time_used, res.memory_used), (990, 2048));
+        let runs = vec![
+            run(1050, 1024, 0, 0, "3"),
+            run(1020, 3072, 0, 0, "4"),
+            run(1090, 2048, 0, 0, "3"),
+        ];
+        let (res, _) = judge_mock(runs, &median).await;
+        assert_eq!(res.result, JudgeResultEnum::TimeLimitExceeded as i32);
+        assert_eq!((res.time_used, res.memory_used), (1050, 1024));
+
+        // 第一次运行留下的文件在重新运行前被清除
+        let mut planted = run(1050, 1024, 0, 0, "3");
+        planted.files = vec![(String::from("planted"), String::from("990"))];
+        let (res, backend) = judge_mock(vec![planted, run(990, 1024, 0, 0, "3")], &minimum).await;
+        assert_eq!(res.runs, 2);
+        let workdirs = backend.workdirs();
+        assert!(!workdirs[0].contains(&String::from("planted")));
+        assert!(!workdirs[1].contains(&String::from("planted")));
+
+        // 运行次数有上限，负数是无效的请求
+        median.rerun.as_mut().unwrap().runs = i32::MAX;
+        let runs = (0..MAX_RUNS).map(|_| run(1050, 1024, 0, 0, "3")).collect();
+        let (res, backend) = judge_mock(runs, &median).await;
+        assert_eq!(res.runs, MAX_RUNS);
+        assert_eq!(backend.spawned().len(), MAX_RUNS as usize);
+        median.rerun.as_mut().unwrap().runs = -1;
+        let cache = tempdir().unwrap();
+        let (res, backend) = judge_mock_in(&language(), vec![], &median, cache.path()).await;
+        assert!(matches!(res, Err(Error::InvalidRequest(_))));
+        assert!(backend.spawned().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stack_limit() {
+        let (_, backend) = judge_mock(vec![run(10, 1024, 0, 0, "3")], &data(false)).await;
+        assert_eq!(backend.spawned()[0].limits.stack, 0);
+
+        // -1 表示与内存限制相同
+        let mut stack = data(false);
+        stack.stack_limit = -1;
+        let (_, backend) = judge_mock(vec![run(10, 1024, 0, 0, "3")], &stack).await;
+        assert_eq!(backend.spawned()[0].limits.stack, 65536);
+
+        stack.stack_limit = 1024;
+        let (_, backend) = judge_mock(vec![run(10, 1024, 0, 0, "3")], &stack).await;
+        assert_eq!(backend.spawned()[0].limits.stack, 1024);
+
+        stack.stack_limit = -2;
+        let cache = tempdir().unwrap();
+        let (res, backend) = judge_mock_in(&language(), vec![], &stack, cache.path()).await;
+        assert!(matches!(res, Err(Error::InvalidRequest(_))));
+        assert!(backend.spawned().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_special_judge() {
+        let mut special = data(false);
+        special.judge_type = JudgeType::Special as i32;
+        special.spj_file = String::from("spj");
+        let runs = vec![run(10, 1024, 0, 0, "4"), run(5, 1024, 0, 0, "ok")];
+        let (res, backend) = judge_mock(runs, &special).await;
+        assert_eq!(res.result, JudgeResultEnum::Accepted as i32);
+        assert_eq!(res.outmsg, "ok");
+        let checker = &backend.spawned()[1];
+        assert_eq!(
+            checker.inner_args,
+            vec![
+                SPJ_FILENAME,
+                SPJ_INPUT_FILENAME,
+                STDOUT_FILENAME,
+                SPJ_ANSWER_FILENAME
+            ]
+        );
+        // 测试数据不会被复制，而是交给沙盒只读挂载
+        assert!(checker.workdir.ends_with(CHECKER_DIRNAME));
+        let binds: Vec<(&str, &str)> = checker
+            .binds
+            .iter()
+            .map(|(source, name)| {
+                let file = Path::new(source).file_name().unwrap().to_str().unwrap();
+                (file, name.as_str())
+            })
+            .collect();
+        assert_eq!(binds[0], ("1.in", SPJ_INPUT_FILENAME));
+        assert_eq!(binds[2], ("1.out", SPJ_ANSWER_FILENAME));
+        // 选手的输出通过打开的文件描述符挂载
+        assert!(checker.binds[1].0.starts_with("/proc/self/fd/"));
+        assert_eq!(binds[1].1, STDOUT_FILENAME);
+        assert!(!Path::new(&checker.workdir)
+            .join(SPJ_INPUT_FILENAME)
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn test_checker_limits() {
+        let mut special = data(false);
+        special.judge_type = JudgeType::Special as i32;
+        special.spj_file = String::from("spj");
+        let runs = vec![run(10, 1024, 0, 0, "4"), run(5, 1024, 1, 0, "wrong")];
+        let (res, backend) = judge_mock(runs, &special).await;
+        assert_eq!(res.result, JudgeResultEnum::WrongAnswer as i32);
+        let limits = &backend.spawned()[1].limits;
+        assert_eq!(limits.time, SPJ_TIME_LIMIT);
+        assert_eq!(limits.memory, SPJ_MEMORY_LIMIT);
+
+        special.spj_time_limit = 2000;
+        special.spj_memory_limit = 65536;
+        let runs = vec![run(10, 1024, 0, 0, "4"), run(2001, 1024, 0, 9, "")];
+        let (res, backend) = judge_mock(runs, &special).await;
+        assert_eq!(res.result, JudgeResultEnum::SystemError as i32);
+        assert!(res
+            .errmsg
+            .starts_with("checker failed: time limit exceeded"));
+        assert!(res.errmsg.contains("time=2001ms"));
+        assert_eq!(res.error_code, ErrorCode::ProblemConfigError as i32);
+        // 选手程序的资源使用不受影响
+        assert_eq!(res.time_used, 10);
+        let limits = &backend.spawned()[1].limits;
+        assert_eq!(limits.time, 2000);
+        assert_eq!(limits.memory, 65536);
+
+        let runs = vec![run(10, 1024, 0, 0, "4"), run(5, 65537, 1, 0, "")];
+        let (res, _) = judge_mock(runs, &special).await;
+        assert_eq!(res.result, JudgeResultEnum::SystemError as i32);
+        assert!(res.errmsg.contains("memory limit exceeded"));
+        assert!(res.errmsg.contains("memory=65537KiB"));
+        assert_eq!(res.error_code, ErrorCode::ProblemConfigError as i32);
+    }
+
+    #[tokio::test]
+    async fn test_checker_source() {
+        let mut special = data(false);
+        special.judge_type = JudgeType::Special as i32;
+        special.spj_file = String::from("checker.c");
+        special.spj_language = String::from("C");
+        let cache = tempdir().unwrap();
+        let runs = vec![
+            run(10, 1024, 0, 0, "4"),
+            run(100, 1024, 0, 0, ""),
+            run(5, 1024, 0, 0, "ok"),
+        ];
+        let (res, backend) = judge_mock_in(&language(), runs, &special, cache.path()).await;
+        assert_eq!(res.unwrap().result, JudgeResultEnum::Accepted as i32);
+        let spawned = backend.spawned();
+        assert_eq!(spawned[1].inner_args, vec!["gcc", "main.c", "-o", "main"]);
+        let checker = &spawned[2];
+        assert_eq!(
+            checker.inner_args,
+            vec![
+                "./main",
+                SPJ_INPUT_FILENAME,
+                STDOUT_FILENAME,
+                SPJ_ANSWER_FILENAME
+            ]
+        );
+        assert_eq!(checker.env, env(&[("MODE", "run")]));
+        let cached = std::fs::read_dir(cache.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(cached.path().join("main.c").is_file());
+
+        // 之后的评测直接使用缓存的编译结果
+        let runs = vec![run(10, 1024, 0, 0, "4"), run(5, 1024, 0, 0, "ok")];
+        let (res, backend) = judge_mock_in(&language(), runs, &special, cache.path()).await;
+        assert_eq!(res.unwrap().result, JudgeResultEnum::Accepted as i32);
+        assert_eq!(backend.spawned().len(), 2);
+        assert_eq!(std::fs::read_dir(cache.path()).unwrap().count(), 1);
+
+        // 编译失败属于题目配置错误，不会被缓存
+        let cache = tempdir().unwrap();
+        let runs = vec![run(10, 1024, 0, 0, "4"), run(100, 1024, 1, 0, "error")];
+        let (res, _) = judge_mock_in(&language(), runs, &special, cache.path()).await;
+        match res {
+            Err(Error::ProblemConfigError(msg)) => assert!(msg.contains("error")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(std::fs::read_dir(cache.path()).unwrap().count(), 0);
+
+        special.spj_language = String::from("Brainfuck");
+        let (res, _) = judge_mock_in(
+            &language(),
+            vec![run(10, 1024, 0, 0, "4")],
+            &special,
+            cache.path(),
+        )
+        .await;
+        assert!(matches!(res, Err(Error::ProblemConfigError(_))));
+    }
+
+    async fn validate_mock(runs: Vec<MockRun>) -> (Result<ValidateResponse>, Arc<MockBackend>) {
+        let data_dir = tempdir().unwrap();
+        for file in &["data1.in", "data1.out", "data2.in", "data2.out"] {
+            std::fs::write(data_dir.path().join(file), "1 2\n").unwrap();
+        }
+        let pwd = tempdir().unwrap();
+        let backend = Arc::new(MockBackend::new(runs));
+        let ctx = context(backend.clone(), data_dir.path(), pwd.path());
+        let request = ValidateRequest {
+            pid: 1000,
+            language: String::from("C"),
+            code: String::from("int main() {}"),
+            time_limit: 2000,
+            ..Default::default()
+        };
+        let res = validate_with(
+            &ctx,
+            &language(),
+            &request,
+            pwd.path(),
+            &Progress::discard(),
+        )
+        .await;
+        (res, backend)
+    }
+
+    #[test]
+    fn test_checker_hash() {
+        let lang = language();
+        let hash = checker_hash(&lang, "int main() {}");
+        assert_eq!(hash, checker_hash(&language(), "int main() {}"));
+        assert_ne!(hash, checker_hash(&lang, "int main() { return 0; }"));
+
+        let mut rootfs = language();
+        rootfs.rootfs = Some(String::from("/rootfs/gcc"));
+        let mut overlay = language();
+        overlay.overlay = vec![String::from("/layers/gcc")];
+        let mut compile_env = language();
+        compile_env
+            .compile_env
+            .insert(String::from("CFLAGS"), String::from("-O2"));
+        let hashes: Vec<String> = [rootfs, overlay, compile_env]
+            .iter()
+            .map(|lang| checker_hash(lang, "int main() {}"))
+            .collect();
+        assert!(hashes.iter().all(|val| *val != hash));
+        assert_ne!(hashes[0], hashes[1]);
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let mut invalid = run(5, 1024, 3, 0, "");
+        invalid.stderr = String::from("FAIL Integer 2 violates the range [1, 1]");
+        let runs = vec![run(100, 1024, 0, 0, ""), run(5, 1024, 0, 0, ""), invalid];
+        let (res, backend) = validate_mock(runs).await;
+        let cases = res.unwrap().cases;
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].r#in, "data1.in");
+        assert!(cases[0].passed);
+        assert!(!cases[1].passed);
+        assert!(cases[1].message.contains("violates the range"));
+        let spawned = backend.spawned();
+        assert_eq!(spawned[0].inner_args, vec!["gcc", "main.c", "-o", "main"]);
+        assert_eq!(spawned[1].inner_args, vec!["./main"]);
+        assert!(spawned[1].stdin.ends_with("data1.in"));
+        assert!(spawned[2].stdin.ends_with("data2.in"));
+        assert_eq!(spawned[1].limits.time, 2000);
+        assert_eq!(spawned[1].limits.memory, SPJ_MEMORY_LIMIT);
+
+        let runs = vec![
+            run(100, 1024, 0, 0, ""),
+            run(2001, 1024, 0, 9, ""),
+            run(5, 1024, 0, 0, ""),
+        ];
+        let (res, _) = validate_mock(runs).await;
+        let cases = res.unwrap().cases;
+        assert!(!cases[0].passed);
+        assert!(cases[0]
+            .message
+            .starts_with("validator failed: time limit exceeded"));
+        assert!(cases[1].passed);
+
+        // 校验器编译失败属于题目配置错误
+        let (res, backend) = validate_mock(vec![run(100, 1024, 1, 0, "error")]).await;
+        assert!(matches!(res, Err(Error::ProblemConfigError(_))));
+        assert_eq!(backend.spawned().len(), 1);
+    }
+
+    async fn generate_mock(
+        data_dir: &Path,
+        runs: Vec<MockRun>,
+        args: &[&str],
+        append: bool,
+    ) -> (Result<GenerateResponse>, Arc<MockBackend>) {
+        let pwd = tempdir().unwrap();
+        let backend = Arc::new(MockBackend::new(runs));
+        let ctx = context(backend.clone(), data_dir, pwd.path());
+        let request = GenerateRequest {
+            pid: 1000,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            append,
+            ..Default::default()
+        };
+        let lang = language();
+        let res = generate_with(
+            &ctx,
+            (&lang, "generator"),
+            (&lang, "solution"),
+            &request,
+            pwd.path(),
+            &Progress::discard(),
+        )
+        .await;
+        (res, backend)
+    }
+
+    #[tokio::test]
+    async fn test_generate() {
+        let data_dir = tempdir().unwrap();
+        // data4 与前面的测试点不连续，不会被列出，但替换时同样需要删除
+        for file in &[
+            "data1.in",
+            "data1.out",
+            "data2.in",
+            "data2.out",
+            "data4.in",
+            "data4.out",
+            "spj.cpp",
+        ] {
+            std::fs::write(data_dir.path().join(file), "old").unwrap();
+        }
+        let read = |file: &str| std::fs::read_to_string(data_dir.path().join(file)).unwrap();
+
+        let compile = || run(100, 1024, 0, 0, "");
+        let runs = vec![
+            compile(),
+            compile(),
+            run(5, 1024, 0, 0, "1 2\n"),
+            run(7, 2048, 0, 0, "3\n"),
+        ];
+        let (res, backend) = generate_mock(data_dir.path(), runs, &["--n 5 'a b'"], false).await;
+        let cases = res.unwrap().cases;
+        assert_eq!(cases.len(), 1);
+        assert_eq!((cases[0].time_used, cases[0].memory_used), (7, 2048));
+        assert_eq!(read("data1.in"), "1 2\n");
+        assert_eq!(read("data1.out"), "3\n");
+        // 替换时删除原有的全部测试点，保留其他文件
+        assert!(!data_dir.path().join("data2.in").exists());
+        assert!(!data_dir.path().join("data4.in").exists());
+        assert!(!data_dir.path().join("data4.out").exists());
+        assert_eq!(read("spj.cpp"), "old");
+        let spawned = backend.spawned();
+        assert_eq!(spawned[2].inner_args, vec!["./main", "--n", "5", "a b"]);
+        assert_eq!(spawned[2].stdin, NO_STDIN);
+        assert!(spawned[3].stdin.ends_with("data1.in"));
+
+        let runs = vec![
+            compile(),
+            compile(),
+            run(5, 1024, 0, 0, "4 5\n"),
+            run(7, 1024, 0, 0, "9\n"),
+        ];
+        let (res, _) = generate_mock(data_dir.path(), runs, &["2"], true).await;
+        assert_eq!(res.unwrap().cases[0].r#in, "data2.in");
+        assert_eq!(read("data1.in"), "1 2\n");
+        assert_eq!(read("data2.out"), "9\n");
+        assert_eq!(read("spj.cpp"), "old");
+
+        // 任何一个测试点失败时都不会修改原有的数据
+        let mut failed = run(5, 1024, 1, 0, "");
+        failed.stderr = String::from("assertion failed");
+        let runs = vec![
+            compile(),
+            compile(),
+            run(5, 1024, 0, 0, "1\n"),
+            run(7, 1024, 0, 0, "1\n"),
+            failed,
+        ];
+        let (res, _) = generate_mock(data_dir.path(), runs, &["3", "4"], false).await;
+        match res {
+            Err(Error::ProblemConfigError(msg)) => {
+                assert!(msg.contains("generator failed"));
+                assert!(msg.contains("assertion failed"));
+                assert!(msg.contains("args #2"));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(read("data1.in"), "1 2\n");
+        assert_eq!(read("data2.in"), "4 5\n");
+
+        let (res, _) = generate_mock(data_dir.path(), vec![], &["'unclosed"], false).await;
+        assert!(matches!(res, Err(Error::InvalidRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_runtime_error() {
+        let (res, _) = judge_mock(vec![run(10, 1024, 0, 11, "")], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::RuntimeError as i32);
+        assert!(res.errmsg.contains("SIGSEGV"));
+
+        let (res, _) = judge_mock(vec![run(10, 1024, 1, 0, "3")], &data(false)).await;
+        assert_eq!(res.result, JudgeResultEnum::RuntimeError as i32);
+    }
+
+    #[tokio::test]
+    async fn test_read_message() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("message.txt");
+
+        std::fs::write(&file, "").unwrap();
+        assert_eq!(read_message(file.clone(), 8).await.unwrap(), "");
+
+        // NUL 与其他二进制数据不会截断消息
+        std::fs::write(&file, b"a\0b\xffc").unwrap();
+        assert_eq!(
+            read_message(file.clone(), 8).await.unwrap(),
+            "a\0b\u{fffd}c"
+        );
+
+        std::fs::write(&file, "12345678").unwrap();
+        assert_eq!(read_message(file.clone(), 8).await.unwrap(), "12345678");
+        std::fs::write(&file, "123456789").unwrap();
+        assert_eq!(
+            read_message(file.clone(), 8).await.unwrap(),
+            "12345678\n[truncated, 9 bytes in total]"
+        );
+
+        // 跨越边界的多字节字符被整个丢弃
+        std::fs::write(&file, "12345你好").unwrap();
+        assert_eq!(
+            read_message(file.clone(), 7).await.unwrap(),
+            "12345\n[truncated, 11 bytes in total]"
+        );
+        assert_eq!(
+            read_message(file.clone(), 8).await.unwrap(),
+            "12345你\n[truncated, 11 bytes in total]"
+        );
+        std::fs::write(&file, "1234567😀").unwrap();
+        assert_eq!(
+            read_message(file.clone(), 10).await.unwrap(),
+            "1234567\n[truncated, 11 bytes in total]"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_output() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("stdout.txt");
+        std::fs::write(&file, "3\n").unwrap();
+        assert!(open_output(&file).await.is_ok());
+
+        // 符号链接与普通文件之外的文件都会被拒绝
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        assert!(open_output(&link).await.is_err());
+        assert!(read_file_head(link.clone(), 8).await.is_err());
+        assert!(read_file_tail(link.clone(), 8).await.is_err());
+        assert_eq!(read_file_tail(file.clone(), 8).await.unwrap(), "3\n");
+        assert!(open_output(dir.path()).await.is_err());
+        let fifo = dir.path().join("fifo");
+        nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::S_IRWXU).unwrap();
+        assert!(open_output(&fifo).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_workdir() {
+        let data_dir = tempdir().unwrap();
+        std::fs::write(data_dir.path().join("1.in"), "1 2\n").unwrap();
+        std::fs::write(data_dir.path().join("1.out"), "3\n").unwrap();
+        let pwd = tempdir().unwrap();
+        let build = pwd.path().join(BUILD_DIRNAME);
+        std::fs::create_dir_all(build.join("lib")).unwrap();
+        std::fs::write(build.join("main"), "binary").unwrap();
+        std::fs::write(build.join("lib").join("a.so"), "lib").unwrap();
+        let run_dir = pwd.path().join(RUN_DIRNAME);
+        // 上一个测试点留下的文件
+        std::fs::create_dir(&run_dir).unwrap();
+        std::fs::write(run_dir.join("planted"), "").unwrap();
+
+        let backend = Arc::new(MockBackend::new(vec![run(10, 1024, 0, 0, "3")]));
+        let ctx = context(backend.clone(), data_dir.path(), pwd.path());
+        let res = judge_with(
+            &ctx,
+            "C",
+            &language(),
+            &data(false),
+            pwd.path(),
+            &Progress::discard(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(res.state, Some(State::Result(_))));
+        let spawned = &backend.spawned()[0];
+        assert_eq!(spawned.workdir, run_dir.to_str().unwrap());
+        // 输出文件不在沙盒内的程序可以修改的工作目录中
+        for file in &[&spawned.stdout, &spawned.stderr, &spawned.result] {
+            assert!(Path::new(file).starts_with(pwd.path().join(OUTPUT_DIRNAME)));
+        }
+        assert!(!run_dir.join("planted").exists());
+        assert_eq!(
+            std::fs::read_to_string(run_dir.join("main")).unwrap(),
+            "binary"
+        );
+        assert!(run_dir.join("lib").join("a.so").is_file());
+    }
+
+    #[tokio::test]
+    async fn test_test_data_not_found() {
+        let data_dir = tempdir().unwrap();
+        let pwd = tempdir().unwrap();
+        let backend = Arc::new(MockBackend::new(vec![]));
+        let ctx = context(backend.clone(), data_dir.path(), pwd.path());
+        let res = judge_with(
+            &ctx,
+            "C",
+            &language(),
+            &data(false),
+            pwd.path(),
+            &Progress::discard(),
+        )
+        .await;
+        assert!(matches!(res, Err(Error::TestDataNotFound(_))));
+        assert!(backend.spawned().is_empty());
     }
-    try_io!(file.read(&mut buffer[offset..]).await);
-    Ok(String::from(String::from_utf8_lossy(&buffer[..offset])))
 }
@@ -2,30 +2,37 @@
 #[macro_use]
 extern crate log;
 
-use std::path::Path;
+use std::env;
 use std::pin::Pin;
+use std::process;
 
-use futures::StreamExt;
+use futures::{stream, StreamExt};
 use futures_core::Stream;
-use log4rs;
 use tempfile::tempdir_in;
-use tokio::fs::read_dir;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
 use tonic::transport::Server;
 use tonic::{Request, Response, Status};
 
+use progress::Progress;
 use river::judge_request::Data;
 use river::river_server::{River, RiverServer};
 use river::{
-    Empty, JudgeRequest, JudgeResponse, JudgeResultEnum, LanguageConfigResponse, LanguageItem,
-    LsCase, LsRequest, LsResponse,
+    Empty, GenerateRequest, GenerateResponse, JudgeRequest, JudgeResponse, JudgeResultEnum,
+    LanguageConfigResponse, LanguageItem, LsRequest, LsResponse, ValidateRequest, ValidateResponse,
 };
 
 mod config;
 mod error;
 
+mod cpus;
+
 mod judger;
+mod problem;
+mod progress;
 mod result;
 mod sandbox;
+mod scheduler;
 
 pub mod river {
     tonic::include_proto!("river");
@@ -59,51 +66,86 @@ impl River for RiverService {
             // 是否通过编译
             let mut compile_success = false;
             let mut language = String::from("");
+            // 测试点序号，用于进度事件
+            let mut case_index = 0;
             while let Some(req) = stream.next().await {
-                yield result::pending();
-                yield result::running();
                 let req = req?;
-                let result = match &req.data {
-                    Some(Data::CompileData(data)) => {
-                        // 因为评测时还需要 language 的信息，因此此处进行复制保存
-                        language = String::from(&data.language);
-                        let res = judger::compile(&language, &data.code, &pwd.path()).await;
-                        // 判断编译结果
-                        if let Ok(ref val) = res {
-                            if let Some(river::judge_response::State::Result(rst)) = &val.state {
-                                if rst.result == JudgeResultEnum::CompileSuccess as i32 {
-                                    // 标记编译成功
-                                    compile_success = true;
+                // 评测过程中的排队、运行、进度事件与最终结果都通过 tx 按顺序推送
+                let (tx, rx) = mpsc::unbounded_channel();
+                // 请求本身有误时以 gRPC 错误结束整个评测流
+                let mut failure = None;
+                let task = async {
+                    let progress = match &req.data {
+                        Some(Data::JudgeData(data)) => {
+                            case_index += 1;
+                            let case_id = if data.case_id.is_empty() {
+                                &data.in_file
+                            } else {
+                                &data.case_id
+                            };
+                            Progress::case(tx, case_index, case_id)
+                        },
+                        _ => Progress::new(tx),
+                    };
+                    let result = match &req.data {
+                        Some(Data::CompileData(data)) => {
+                            // 因为评测时还需要 language 的信息，因此此处进行复制保存
+                            language = String::from(&data.language);
+                            let res = judger::compile(&language, &data.code, pwd.path(), &progress).await;
+                            // 判断编译结果
+                            if let Ok(ref val) = res {
+                                if let Some(river::judge_response::State::Result(rst)) = &val.state {
+                                    if rst.result == JudgeResultEnum::CompileSuccess as i32 {
+                                        // 标记编译成功
+                                        compile_success = true;
+                                    }
                                 }
                             }
-                        }
-                        res
-                    },
-                    Some(Data::JudgeData(data)) => {
-                        // 必须通过编译才能运行
-                        if language == "" || !compile_success {
-                            Err(error::Error::CustomError(String::from("not compiled")))
-                        } else {
-                            judger::judge(
-                                &language,
-                                &data.in_file,
-                                &data.out_file,
-                                &data.spj_file,
-                                data.time_limit,
-                                data.memory_limit,
-                                data.judge_type,
-                                &pwd.path()
-                            ).await
-                        }
-                    },
-                    None => Err(error::Error::CustomError(String::from("unrecognized request types"))),
-                };
-                let res = match result {
-                    Ok(res) => res,
-                    Err(e) => result::system_error(e)
+                            res
+                        },
+                        Some(Data::JudgeData(data)) => {
+                            // 必须通过编译才能运行
+                            if language.is_empty() || !compile_success {
+                                Err(error::Error::NotCompiled())
+                            } else {
+                                judger::judge(&language, data, pwd.path(), &progress).await
+                            }
+                        },
+                        None => Err(error::Error::InvalidRequest(String::from("unrecognized request types"))),
+                    };
+                    let res = match result {
+                        Ok(res) => res,
+                        Err(e) if e.is_client_error() => {
+                            warn!("path: {}, rejected: {}", path_str, e);
+                            failure = Some(Status::from(e));
+                            return;
+                        },
+                        Err(e) => result::system_error(e)
+                    };
+                    match &req.data {
+                        Some(Data::CompileData(_)) => progress.compile_finished(&res),
+                        Some(Data::JudgeData(_)) => progress.case_finished(&res),
+                        None => {},
+                    }
+                    info!("path: {}, result: {:?}", path_str, res);
+                    progress.send(res);
                 };
-                info!("path: {}, result: {:?}", path_str, res);
-                yield res;
+                {
+                    // task 结束时 tx 随之释放，rx 中剩余的消息读取完毕后 events 结束
+                    let events = stream::select(
+                        UnboundedReceiverStream::new(rx).map(Some),
+                        stream::once(task).map(|_| None),
+                    );
+                    futures::pin_mut!(events);
+                    while let Some(event) = events.next().await {
+                        if let Some(res) = event {
+                            yield res;
+                        }
+                    }
+                }
+                if let Some(status) = failure {
+                    Err(status)?;
+                }
             };
             info!("request end on `{}`", path_str);
         };
@@ -119,8 +161,8 @@ impl River for RiverService {
         for (key, value) in &config::CONFIG.languages {
             languages.push(LanguageItem {
                 language: String::from(key),
-                compile: String::from(&value.compile_cmd),
-                run: String::from(&value.run_cmd),
+                compile: value.compile_cmd.to_string(),
+                run: value.run_cmd.to_string(),
                 version: String::from(&value.version),
             });
         }
@@ -130,39 +172,63 @@ impl River for RiverService {
 
     async fn ls(&self, request: Request<LsRequest>) -> Result<Response<LsResponse>, Status> {
         let pid = request.into_inner().pid;
-        let mut response = LsResponse { cases: vec![] };
-        let mut directory_stream =
-            match read_dir(Path::new(&config::CONFIG.data_dir).join(pid.to_string())).await {
-                Ok(val) => val,
-                Err(_) => return Ok(Response::new(response)),
-            };
-        let mut files: Vec<String> = vec![];
-        while let Ok(Some(entry)) = directory_stream.next_entry().await {
-            let file = entry.file_name().into_string().unwrap();
-            files.push(file);
+        let cases = problem::cases(&problem::dir(pid)).await;
+        Ok(Response::new(LsResponse { cases }))
+    }
+
+    async fn validate(
+        &self,
+        request: Request<ValidateRequest>,
+    ) -> Result<Response<ValidateResponse>, Status> {
+        let request = request.into_inner();
+        let pwd = match tempdir_in(&config::CONFIG.judge_dir) {
+            Ok(val) => val,
+            Err(e) => return Err(Status::from(error::Error::IOError(e))),
+        };
+        match judger::validate(&request, pwd.path()).await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(e) => {
+                warn!("validate pid {} failed: {}", request.pid, e);
+                Err(Status::from(e))
+            }
         }
-        let mut iter = 1;
-        loop {
-            let in_file = format!("data{}.in", iter);
-            let out_file = format!("data{}.out", iter);
-            if files.contains(&in_file) && files.contains(&out_file) {
-                response.cases.push(LsCase {
-                    r#in: in_file,
-                    out: out_file,
-                });
-                iter += 1;
-            } else {
-                break;
+    }
+
+    async fn generate(
+        &self,
+        request: Request<GenerateRequest>,
+    ) -> Result<Response<GenerateResponse>, Status> {
+        let request = request.into_inner();
+        let pwd = match tempdir_in(&config::CONFIG.judge_dir) {
+            Ok(val) => val,
+            Err(e) => return Err(Status::from(error::Error::IOError(e))),
+        };
+        match judger::generate(&request, pwd.path()).await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(e) => {
+                warn!("generate pid {} failed: {}", request.pid, e);
+                Err(Status::from(e))
             }
         }
-        Ok(Response::new(response))
     }
 }
 
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // 网络隔离自检时，River 自身会作为探测程序在沙盒内运行
+    if env::args().nth(1).as_deref() == Some(sandbox::PROBE_ARG) {
+        process::exit(sandbox::probe());
+    }
+    serve()
+}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn serve() -> Result<(), Box<dyn std::error::Error>> {
     log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
 
+    scheduler::init()?;
+    sandbox::init()?;
+    sandbox::self_test().await?;
+
     let addr = "0.0.0.0:4003".parse()?;
     let river = RiverService::default();
 
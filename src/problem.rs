@@ -0,0 +1,184 @@
+use std::ffi::CString;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+use tokio::fs::read_dir;
+
+use crate::config::CONFIG;
+use crate::error::{Error, Result};
+use crate::river::LsCase;
+
+/// 题目的测试数据目录：data_dir/<pid>
+pub fn dir(pid: i32) -> PathBuf {
+    Path::new(&CONFIG.data_dir).join(pid.to_string())
+}
+
+/// 第 index 个测试点（从 1 开始）的输入与输出文件名
+pub fn case_files(index: usize) -> (String, String) {
+    (format!("data{}.in", index), format!("data{}.out", index))
+}
+
+/// 按编号列出题目的测试点，编号从 1 开始，遇到缺少输入或输出文件的编号时结束
+pub async fn cases(dir: &Path) -> Vec<LsCase> {
+    let mut cases = vec![];
+    let mut directory_stream = match read_dir(dir).await {
+        Ok(val) => val,
+        Err(_) => return cases,
+    };
+    let mut files: Vec<String> = vec![];
+    while let Ok(Some(entry)) = directory_stream.next_entry().await {
+        if let Ok(file) = entry.file_name().into_string() {
+            files.push(file);
+        }
+    }
+    for index in 1.. {
+        let (in_file, out_file) = case_files(index);
+        if !files.contains(&in_file) || !files.contains(&out_file) {
+            break;
+        }
+        cases.push(LsCase {
+            r#in: in_file,
+            out: out_file,
+        });
+    }
+    cases
+}
+
+/// 文件名是否为测试点的输入或输出文件，即 dataN.in 或 dataN.out
+pub fn is_case_file(name: &str) -> bool {
+    let index = match name
+        .strip_prefix("data")
+        .and_then(|s| s.strip_suffix(".in").or_else(|| s.strip_suffix(".out")))
+    {
+        Some(val) => val,
+        None => return false,
+    };
+    !index.is_empty() && index.bytes().all(|c| c.is_ascii_digit())
+}
+
+/// 用 staging 中的 files 更新测试数据目录 dir
+///
+/// 新的目录先在 dir 旁边构建好，再与原目录原子地交换，评测不会看到只更新了一部分的数据。
+/// 原目录中的其他文件以硬链接的方式保留；append 为 false 时丢弃原有的全部测试点，
+/// 包括编号不连续、不会被 `cases` 列出的测试点。
+pub async fn replace_cases(dir: &Path, staging: &Path, files: &[&str], append: bool) -> Result<()> {
+    let parent = match dir.parent() {
+        Some(val) => val,
+        None => return Err(Error::PathToStringError()),
+    };
+    try_io!(fs::create_dir_all(parent).await);
+    let tmp = try_io!(tempfile::Builder::new().prefix(".").tempdir_in(parent));
+    for file in files {
+        try_io!(fs::copy(staging.join(file), tmp.path().join(file)).await);
+    }
+
+    let permissions = match fs::metadata(dir).await {
+        Ok(val) => Some(val.permissions()),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(Error::IOError(e)),
+    };
+    let permissions = match permissions {
+        Some(val) => {
+            link_dir(dir, tmp.path(), |name| {
+                // 新生成的测试点覆盖原有的同名文件
+                !files.contains(&name) && (append || !is_case_file(name))
+            })
+            .await?;
+            val
+        }
+        None => PermissionsExt::from_mode(0o755),
+    };
+    try_io!(fs::set_permissions(tmp.path(), permissions).await);
+
+    if !dir.exists() {
+        try_io!(fs::rename(tmp.path(), dir).await);
+        return Ok(());
+    }
+    let from = cstring(tmp.path())?;
+    let to = cstring(dir)?;
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::IOError(std::io::Error::last_os_error()));
+    }
+    // 交换之后 tmp 中是原来的数据，随 tmp 一起删除
+    Ok(())
+}
+
+// 把 from 中的文件以硬链接的方式放入 to，子目录递归处理，keep 只作用于 from 的顶层
+async fn link_dir<F: Fn(&str) -> bool>(from: &Path, to: &Path, keep: F) -> Result<()> {
+    let mut dirs = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from_dir, to_dir)) = dirs.pop() {
+        let mut entries = try_io!(fs::read_dir(&from_dir).await);
+        while let Some(entry) = try_io!(entries.next_entry().await) {
+            if from_dir == from && !keep(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let target = to_dir.join(entry.file_name());
+            let file_type = try_io!(entry.file_type().await);
+            if file_type.is_dir() {
+                try_io!(fs::create_dir(&target).await);
+                let permissions = try_io!(entry.metadata().await).permissions();
+                try_io!(fs::set_permissions(&target, permissions).await);
+                dirs.push((entry.path(), target));
+            } else if file_type.is_symlink() {
+                let link = try_io!(fs::read_link(entry.path()).await);
+                try_io!(fs::symlink(link, &target).await);
+            } else {
+                try_io!(fs::hard_link(entry.path(), &target).await);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn cstring(path: &Path) -> Result<CString> {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(val) => Ok(val),
+        Err(e) => Err(Error::StringToCStringError(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_cases() {
+        let dir = tempdir().unwrap();
+        assert!(cases(&dir.path().join("missing")).await.is_empty());
+        for file in &["data1.in", "data1.out", "data2.in", "data2.out", "data3.in"] {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        // 没有 data3.out，因此之后的测试点也不会被列出
+        std::fs::write(dir.path().join("data4.in"), "").unwrap();
+        std::fs::write(dir.path().join("data4.out"), "").unwrap();
+        let files: Vec<String> = cases(dir.path())
+            .await
+            .into_iter()
+            .map(|case| case.r#in)
+            .collect();
+        assert_eq!(files, vec!["data1.in", "data2.in"]);
+    }
+
+    #[test]
+    fn test_is_case_file() {
+        assert!(is_case_file("data1.in"));
+        assert!(is_case_file("data12.out"));
+        assert!(!is_case_file("data.in"));
+        assert!(!is_case_file("data1a.in"));
+        assert!(!is_case_file("data1.ans"));
+        assert!(!is_case_file("spj.cpp"));
+    }
+}
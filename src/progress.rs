@@ -0,0 +1,76 @@
+use tokio::sync::mpsc;
+
+use crate::result::{event, running, Sender};
+use crate::river::judge_response::State;
+use crate::river::{JudgeEventType, JudgeResponse};
+
+/// 评测进度的推送句柄
+///
+/// 除了排队、运行等状态外，还会附带当前测试点的序号与标识，以便生成结构化的进度事件。
+pub struct Progress {
+    tx: Sender,
+    case_index: i32,
+    case_id: String,
+}
+
+impl Progress {
+    pub fn new(tx: Sender) -> Self {
+        Progress {
+            tx,
+            case_index: 0,
+            case_id: String::from(""),
+        }
+    }
+
+    /// 不需要推送进度时使用，所有消息都会被丢弃
+    pub fn discard() -> Self {
+        let (tx, _) = mpsc::unbounded_channel();
+        Progress::new(tx)
+    }
+
+    /// 为某个测试点创建推送句柄
+    pub fn case(tx: Sender, case_index: i32, case_id: &str) -> Self {
+        Progress {
+            tx,
+            case_index,
+            case_id: String::from(case_id),
+        }
+    }
+
+    pub fn send(&self, res: JudgeResponse) {
+        // 客户端已经断开时没有必要继续推送
+        let _ = self.tx.send(res);
+    }
+
+    pub fn compile_started(&self) {
+        self.send(running());
+        self.emit(JudgeEventType::CompileStarted, None);
+    }
+
+    pub fn compile_finished(&self, res: &JudgeResponse) {
+        self.emit(JudgeEventType::CompileFinished, Some(res));
+    }
+
+    pub fn case_started(&self) {
+        self.send(running());
+        self.emit(JudgeEventType::CaseStarted, None);
+    }
+
+    pub fn case_finished(&self, res: &JudgeResponse) {
+        self.emit(JudgeEventType::CaseFinished, Some(res));
+    }
+
+    pub fn checker_running(&self) {
+        self.emit(JudgeEventType::CheckerRunning, None);
+    }
+
+    fn emit(&self, event_type: JudgeEventType, res: Option<&JudgeResponse>) {
+        let result = match res {
+            Some(JudgeResponse {
+                state: Some(State::Result(result)),
+            }) => Some(result.clone()),
+            _ => None,
+        };
+        self.send(event(event_type, self.case_index, &self.case_id, result));
+    }
+}
@@ -1,7 +1,17 @@
+use std::convert::TryFrom;
+
+use nix::sys::signal::Signal;
+
 use crate::error::Error;
 use crate::error::Result;
 use crate::river::judge_response::State;
-use crate::river::{JudgeResponse, JudgeResult, JudgeResultEnum, JudgeStatus};
+use crate::river::{
+    Difference, ErrorCode, JudgeEvent, JudgeEventType, JudgeResponse, JudgeResult, JudgeResultEnum,
+    JudgeStatus, PendingStatus,
+};
+
+/// 评测过程中向客户端推送中间状态的通道
+pub type Sender = tokio::sync::mpsc::UnboundedSender<JudgeResponse>;
 
 pub fn system_error(err: Error) -> JudgeResponse {
     warn!("{}", err);
@@ -10,15 +20,22 @@ pub fn system_error(err: Error) -> JudgeResponse {
             time_used: 0,
             memory_used: 0,
             result: JudgeResultEnum::SystemError as i32,
-            errmsg: format!("{}", err).into(),
+            errmsg: format!("{}", err),
             outmsg: String::from(""),
+            difference: None,
+            error_code: err.code() as i32,
+            real_time_used: 0,
+            runs: 0,
         })),
     }
 }
 
-pub fn pending() -> JudgeResponse {
+pub fn pending(position: i32, estimated_wait: i64) -> JudgeResponse {
     JudgeResponse {
-        state: Some(State::Status(JudgeStatus::Pending as i32)),
+        state: Some(State::Pending(PendingStatus {
+            position,
+            estimated_wait,
+        })),
     }
 }
 
@@ -28,6 +45,22 @@ pub fn running() -> JudgeResponse {
     }
 }
 
+pub fn event(
+    event_type: JudgeEventType,
+    case_index: i32,
+    case_id: &str,
+    result: Option<JudgeResult>,
+) -> JudgeResponse {
+    JudgeResponse {
+        state: Some(State::Event(JudgeEvent {
+            r#type: event_type as i32,
+            case_index,
+            case_id: String::from(case_id),
+            result,
+        })),
+    }
+}
+
 pub fn compile_error(time_used: i64, memory_used: i64, errmsg: &str) -> JudgeResponse {
     JudgeResponse {
         state: Some(State::Result(JudgeResult {
@@ -36,6 +69,10 @@ pub fn compile_error(time_used: i64, memory_used: i64, errmsg: &str) -> JudgeRes
             result: JudgeResultEnum::CompileError as i32,
             errmsg: String::from(errmsg),
             outmsg: String::from(""),
+            difference: None,
+            error_code: ErrorCode::NoError as i32,
+            real_time_used: 0,
+            runs: 0,
         })),
     }
 }
@@ -52,6 +89,28 @@ pub fn wrong_answer(time_used: i64, memory_used: i64) -> JudgeResponse {
     judge_result(time_used, memory_used, JudgeResultEnum::WrongAnswer)
 }
 
+pub fn wrong_answer_detail(
+    time_used: i64,
+    memory_used: i64,
+    difference: Difference,
+    outmsg: &str,
+    errmsg: &str,
+) -> JudgeResponse {
+    JudgeResponse {
+        state: Some(State::Result(JudgeResult {
+            time_used,
+            memory_used,
+            result: JudgeResultEnum::WrongAnswer as i32,
+            errmsg: String::from(errmsg),
+            outmsg: String::from(outmsg),
+            difference: Some(difference),
+            error_code: ErrorCode::NoError as i32,
+            real_time_used: 0,
+            runs: 0,
+        })),
+    }
+}
+
 pub fn time_limit_exceeded(time_used: i64, memory_used: i64) -> JudgeResponse {
     judge_result(time_used, memory_used, JudgeResultEnum::TimeLimitExceeded)
 }
@@ -68,10 +127,54 @@ pub fn runtime_error(time_used: i64, memory_used: i64, errmsg: &str) -> JudgeRes
             result: JudgeResultEnum::RuntimeError as i32,
             errmsg: String::from(errmsg),
             outmsg: String::from(""),
+            difference: None,
+            error_code: ErrorCode::NoError as i32,
+            real_time_used: 0,
+            runs: 0,
+        })),
+    }
+}
+
+pub fn restricted_function(time_used: i64, memory_used: i64, syscall: &str) -> JudgeResponse {
+    JudgeResponse {
+        state: Some(State::Result(JudgeResult {
+            time_used,
+            memory_used,
+            result: JudgeResultEnum::RestrictedFunction as i32,
+            errmsg: format!("Restricted system call: `{}`", syscall),
+            outmsg: String::from(""),
+            difference: None,
+            error_code: ErrorCode::NoError as i32,
+            real_time_used: 0,
+            runs: 0,
         })),
     }
 }
 
+/// 补充程序运行的墙上时间与运行次数
+pub fn with_timing(mut res: JudgeResponse, real_time_used: i64, runs: usize) -> JudgeResponse {
+    if let Some(State::Result(result)) = &mut res.state {
+        result.real_time_used = real_time_used;
+        result.runs = runs as i32;
+    }
+    res
+}
+
+/// 信号的名称，例如 11 -> SIGSEGV
+pub fn signal_name(signal: i64) -> String {
+    match Signal::try_from(signal as i32) {
+        Ok(sig) => String::from(sig.as_str()),
+        Err(_) => format!("signal {}", signal),
+    }
+}
+
+/// 去除程序输出中的控制字符（保留换行与制表符），以便安全地展示给用户
+pub fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|ch| !ch.is_control() || *ch == '\n' || *ch == '\t')
+        .collect()
+}
+
 fn judge_result(time_used: i64, memory_used: i64, result: JudgeResultEnum) -> JudgeResponse {
     JudgeResponse {
         state: Some(State::Result(JudgeResult {
@@ -80,6 +183,27 @@ fn judge_result(time_used: i64, memory_used: i64, result: JudgeResultEnum) -> Ju
             result: result as i32,
             errmsg: String::from(""),
             outmsg: String::from(""),
+            difference: None,
+            error_code: ErrorCode::NoError as i32,
+            real_time_used: 0,
+            runs: 0,
+        })),
+    }
+}
+
+/// spj 自身运行失败，选手程序的结果未知，通常是题目的 spj 有误
+pub fn checker_error(time_used: i64, memory_used: i64, errmsg: &str) -> JudgeResponse {
+    JudgeResponse {
+        state: Some(State::Result(JudgeResult {
+            time_used,
+            memory_used,
+            result: JudgeResultEnum::SystemError as i32,
+            errmsg: String::from(errmsg),
+            outmsg: String::from(""),
+            difference: None,
+            error_code: ErrorCode::ProblemConfigError as i32,
+            real_time_used: 0,
+            runs: 0,
         })),
     }
 }
@@ -98,48 +222,88 @@ pub fn spj_result(
             result: result as i32,
             errmsg: String::from(errmsg),
             outmsg: String::from(outmsg),
+            difference: None,
+            error_code: ErrorCode::NoError as i32,
+            real_time_used: 0,
+            runs: 0,
         })),
     }
 }
 
 pub fn standard_result(out: &[u8], ans: &[u8]) -> Result<JudgeResultEnum> {
+    // 没有 PE，PE 直接 WA
+    if standard_diff(out, ans, 0).is_some() {
+        Ok(JudgeResultEnum::WrongAnswer)
+    } else {
+        Ok(JudgeResultEnum::Accepted)
+    }
+}
+
+/**
+ * 逐行对比程序输出与答案，返回第一处不同
+ * 行号为原始文件中的行号，对比的规则与 next_line 相同
+ * 行内容最多保留 limit 个字节，limit 为 0 时不返回行内容
+ */
+pub fn standard_diff(out: &[u8], ans: &[u8], limit: usize) -> Option<Difference> {
     let out_len = out.len();
     let ans_len = ans.len();
     let mut out_offset = 0;
     let mut ans_offset = 0;
-    // 没有 PE，PE 直接 WA
-    let mut r = JudgeResultEnum::Accepted;
     while out_offset <= out_len && ans_offset <= ans_len {
-        let (out_start, out_end, out_exists) = next_line(&out, out_offset, out_len);
-        let (ans_start, ans_end, ans_exists) = next_line(&ans, ans_offset, ans_len);
-        if !out_exists || !ans_exists {
-            // 如果一个已经读取完但另一个还有数据，则结果为 WA
-            if out_exists != ans_exists {
-                r = JudgeResultEnum::WrongAnswer;
-            }
-            break;
-        }
+        let (out_start, out_end, out_exists) = next_line(out, out_offset, out_len);
+        let (ans_start, ans_end, ans_exists) = next_line(ans, ans_offset, ans_len);
+        // 如果一个已经读取完但另一个还有数据，则结果为 WA
         // 如果两个数据当前行长度不同，则结果为 WA（这个长度已经排除了末尾空白符号）
-        if out_end - out_start != ans_end - ans_start {
-            r = JudgeResultEnum::WrongAnswer;
-            break;
-        }
-        let line_len = out_end - out_start;
-        for i in 0..line_len {
-            // 逐个对比
-            if out[out_start + i] != ans[ans_start + i] {
-                r = JudgeResultEnum::WrongAnswer;
-                break;
-            }
+        // 否则逐个对比
+        let same = if !out_exists || !ans_exists {
+            out_exists == ans_exists
+        } else {
+            out[out_start..out_end] == ans[ans_start..ans_end]
+        };
+        if !same {
+            let (out_start, out_end) = if out_exists {
+                (out_start, out_end)
+            } else {
+                (out_len, out_len)
+            };
+            let (ans_start, ans_end) = if ans_exists {
+                (ans_start, ans_end)
+            } else {
+                (ans_len, ans_len)
+            };
+            return Some(Difference {
+                expected_line: line_number(ans, ans_start),
+                actual_line: line_number(out, out_start),
+                expected: snippet(&ans[ans_start..ans_end], limit),
+                actual: snippet(&out[out_start..out_end], limit),
+            });
         }
-        // 如果结果出来了，则退出循环
-        if r != JudgeResultEnum::Accepted {
+        if !out_exists {
             break;
         }
         out_offset = out_end;
         ans_offset = ans_end;
     }
-    Ok(r)
+    None
+}
+
+// offset 所在的行号，从 1 开始
+fn line_number(v: &[u8], offset: usize) -> i64 {
+    v[..offset].iter().filter(|ch| **ch == b'\n').count() as i64 + 1
+}
+
+/// 截取最多 limit 个字节的文本，不会截断多字节字符
+pub fn snippet(v: &[u8], limit: usize) -> String {
+    // 先按字节截取，避免转换整个输出；多保留的 3 个字节足以容纳跨越 limit 的最后一个字符
+    let text = String::from_utf8_lossy(&v[..v.len().min(limit + 3)]);
+    if text.len() <= limit {
+        return text.into_owned();
+    }
+    let mut end = limit;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    String::from(&text[..end])
 }
 
 /**
@@ -292,6 +456,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_diff() {
+        let ans: &[u8] = "1\n2\n3\n".as_bytes();
+        let out: &[u8] = "1\n\n2\n4\n".as_bytes();
+        let diff = standard_diff(out, ans, 1024).unwrap();
+        assert_eq!(diff.expected_line, 3);
+        assert_eq!(diff.actual_line, 4);
+        assert_eq!(diff.expected, "3");
+        assert_eq!(diff.actual, "4");
+    }
+
+    #[test]
+    fn test_diff_missing_line() {
+        let ans: &[u8] = "Hello\nWorld!".as_bytes();
+        let out: &[u8] = "Hello\n".as_bytes();
+        let diff = standard_diff(out, ans, 3).unwrap();
+        assert_eq!(diff.expected_line, 2);
+        assert_eq!(diff.actual_line, 2);
+        assert_eq!(diff.expected, "Wor");
+        assert_eq!(diff.actual, "");
+        assert!(standard_diff(ans, ans, 3).is_none());
+    }
+
+    #[test]
+    fn test_signal_name() {
+        assert_eq!(signal_name(11), "SIGSEGV");
+        assert_eq!(signal_name(8), "SIGFPE");
+        assert_eq!(signal_name(6), "SIGABRT");
+        assert_eq!(signal_name(1000), "signal 1000");
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(snippet("abc".as_bytes(), 8), "abc");
+        assert_eq!(snippet("ab你好".as_bytes(), 5), "ab你");
+        assert_eq!(snippet("ab你好".as_bytes(), 4), "ab");
+        assert_eq!(snippet(&[b'a'; 1 << 20], 2), "aa");
+        assert_eq!(snippet(b"a\xffb", 4), "a\u{fffd}");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            sanitize("Traceback\x1b[31m\r\n\tline 1\x00"),
+            "Traceback[31m\n\tline 1"
+        );
+    }
+
     #[test]
     fn test10() {
         let ans: &[u8] = "Hello World!".as_bytes();
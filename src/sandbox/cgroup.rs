@@ -0,0 +1,324 @@
+use std::ffi::CString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::error::{Error, Result};
+
+static CGROUP_ROOT: &str = "/sys/fs/cgroup";
+// River 创建的所有 cgroup 都位于此目录下（v1 中位于各个子系统的此目录下）
+static CGROUP_PARENT: &str = "river";
+// 单次运行的 cgroup 名称前缀，完整名称为 river-<pid>-<序号>
+static CGROUP_PREFIX: &str = "river-";
+// v2 中需要为子 cgroup 启用的控制器
+static CONTROLLERS: [&str; 3] = ["memory", "pids", "cpu"];
+// cpu.max 的周期（微秒），每个周期内的配额为绑定的 CPU 数量乘以周期，避免多线程程序挤占其他评测
+static CPU_PERIOD: usize = 100000;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+lazy_static! {
+    // 只有 cgroup2 挂载在根目录上（unified 模式）时才使用 v2，hybrid 模式下仍使用 v1
+    pub static ref VERSION: Version = if Path::new(CGROUP_ROOT).join("cgroup.controllers").is_file() {
+        Version::V2
+    } else {
+        Version::V1
+    };
+}
+
+/// 启动时调用：创建父 cgroup，v2 中启用所需的控制器，并清理上次崩溃后遗留的 cgroup
+pub fn init() -> Result<()> {
+    info!("cgroup version: {:?}", *VERSION);
+    for parent in parents() {
+        try_io!(fs::create_dir_all(&parent));
+        cleanup(&parent);
+    }
+    if *VERSION == Version::V2 {
+        let enabled = CONTROLLERS
+            .iter()
+            .map(|name| format!("+{}", name))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write(
+            &Path::new(CGROUP_ROOT)
+                .join(CGROUP_PARENT)
+                .join("cgroup.subtree_control"),
+            &enabled,
+        )?;
+    }
+    Ok(())
+}
+
+/// 单次运行使用的 cgroup，离开作用域时会杀死其中剩余的进程并删除
+pub struct Cgroup {
+    // v1 中依次为 memory、pids 子系统下的目录，v2 中只有一个目录
+    paths: Vec<PathBuf>,
+}
+
+impl Cgroup {
+    /// memory_limit 的单位为 KiB，memory_limit 与 pids 为 0 时不限制，cpus 为程序可以使用的 CPU 数量
+    pub fn create(memory_limit: i64, pids: i32, cpus: usize) -> Result<Cgroup> {
+        let name = format!(
+            "{}{}-{}",
+            CGROUP_PREFIX,
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let cgroup = Cgroup {
+            paths: parents().into_iter().map(|path| path.join(&name)).collect(),
+        };
+        for path in &cgroup.paths {
+            create(path)?;
+        }
+        let memory_limit = if memory_limit > 0 {
+            (memory_limit * 1024).to_string()
+        } else {
+            String::from(match *VERSION {
+                Version::V1 => "-1",
+                Version::V2 => "max",
+            })
+        };
+        match *VERSION {
+            Version::V1 => {
+                write(
+                    &cgroup.memory().join("memory.limit_in_bytes"),
+                    &memory_limit,
+                )?;
+            }
+            Version::V2 => {
+                write(&cgroup.memory().join("memory.max"), &memory_limit)?;
+                // 超出内存限制时直接触发 OOM，而不是换出到 swap
+                let swap = cgroup.memory().join("memory.swap.max");
+                if swap.is_file() {
+                    write(&swap, "0")?;
+                }
+                write(&cgroup.cpu().join("cpu.max"), &cpu_max(cpus))?;
+            }
+        }
+        if pids > 0 {
+            write(&cgroup.pids().join("pids.max"), &pids.to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    /// 各个 cgroup.procs 文件，子进程向其中写入 0 即可加入
+    pub fn procs(&self) -> Result<Vec<CString>> {
+        let mut procs = vec![];
+        for path in &self.paths {
+            match CString::new(path.join("cgroup.procs").to_string_lossy().as_bytes()) {
+                Ok(val) => procs.push(val),
+                Err(e) => return Err(Error::StringToCStringError(e)),
+            }
+        }
+        Ok(procs)
+    }
+
+    /// 内存使用的峰值，单位为 KiB
+    ///
+    /// 内核低于 5.19 时 v2 没有 memory.peak，此时返回 None，由调用者使用 rusage 中的数据。
+    pub fn memory_used(&self) -> Result<Option<i64>> {
+        let file = match *VERSION {
+            Version::V1 => "memory.max_usage_in_bytes",
+            Version::V2 => "memory.peak",
+        };
+        match fs::read_to_string(self.memory().join(file)) {
+            Ok(text) => Ok(Some(parse(text.trim())? / 1024)),
+            Err(e) if e.kind() == ErrorKind::NotFound && *VERSION == Version::V2 => Ok(None),
+            Err(e) => Err(Error::IOError(e)),
+        }
+    }
+
+    /// cgroup 中所有进程使用的 CPU 时间，单位为毫秒，只有 v2 支持
+    pub fn cpu_used(&self) -> Result<Option<i64>> {
+        if *VERSION == Version::V1 {
+            return Ok(None);
+        }
+        let text = try_io!(fs::read_to_string(self.cpu().join("cpu.stat")));
+        Ok(stat(&text, "usage_usec")?.map(|usec| usec / 1000))
+    }
+
+    /// 是否有进程因超出内存限制被 OOM killer 杀死，内核低于 4.13 时 v1 无法得知，总是返回 false
+    pub fn oom_killed(&self) -> Result<bool> {
+        let file = match *VERSION {
+            Version::V1 => "memory.oom_control",
+            Version::V2 => "memory.events",
+        };
+        let text = try_io!(fs::read_to_string(self.memory().join(file)));
+        Ok(stat(&text, "oom_kill")?.unwrap_or(0) > 0)
+    }
+
+    fn memory(&self) -> &Path {
+        &self.paths[0]
+    }
+
+    // 只在 v2 中使用
+    fn cpu(&self) -> &Path {
+        &self.paths[0]
+    }
+
+    fn pids(&self) -> &Path {
+        self.paths.last().unwrap()
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            remove(path);
+        }
+    }
+}
+
+fn parents() -> Vec<PathBuf> {
+    let root = Path::new(CGROUP_ROOT);
+    match *VERSION {
+        Version::V1 => vec![
+            root.join("memory").join(CGROUP_PARENT),
+            root.join("pids").join(CGROUP_PARENT),
+        ],
+        Version::V2 => vec![root.join(CGROUP_PARENT)],
+    }
+}
+
+// 删除创建者已经不存在的 cgroup，River 崩溃时来不及清理它们
+fn cleanup(parent: &Path) {
+    let entries = match fs::read_dir(parent) {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if let Some(owner) = owner(&entry.file_name().to_string_lossy()) {
+            if stale(owner) {
+                warn!("remove stale cgroup `{:?}`", entry.path());
+                remove(&entry.path());
+            }
+        }
+    }
+}
+
+// 启动时还没有创建任何 cgroup，与当前进程同名的 cgroup 来自 pid 相同的上一个 River 进程
+fn stale(owner: libc::pid_t) -> bool {
+    owner as u32 == process::id() || !alive(owner)
+}
+
+// 创建新的 cgroup，不能复用已经存在的目录，其中可能残留着上一个进程的限制与统计数据
+fn create(path: &Path) -> Result<()> {
+    match fs::create_dir(path) {
+        Ok(_) => return Ok(()),
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+            warn!("remove stale cgroup `{:?}`", path);
+            remove(path);
+        }
+        Err(e) => return Err(Error::IOError(e)),
+    }
+    try_io!(fs::create_dir(path));
+    Ok(())
+}
+
+// 从 cgroup 名称中解析出创建它的 River 进程
+fn owner(name: &str) -> Option<libc::pid_t> {
+    name.strip_prefix(CGROUP_PREFIX)
+        .and_then(|rest| rest.split('-').next())
+        .and_then(|pid| pid.parse().ok())
+}
+
+// 从 `key value` 格式的统计文件中读取一项
+fn stat(text: &str, key: &str) -> Result<Option<i64>> {
+    for line in text.lines() {
+        if let Some((name, value)) = line.split_once(' ') {
+            if name == key {
+                return Ok(Some(parse(value.trim())?));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn alive(pid: libc::pid_t) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+fn parse(value: &str) -> Result<i64> {
+    match value.parse::<i64>() {
+        Ok(val) => Ok(val),
+        Err(e) => Err(Error::ParseIntError(e)),
+    }
+}
+
+fn write(path: &Path, value: &str) -> Result<()> {
+    try_io!(fs::write(path, value));
+    Ok(())
+}
+
+// 杀死 cgroup 中剩余的进程后删除，进程退出需要一点时间，因此会重试几次
+fn remove(path: &Path) {
+    for _ in 0..10 {
+        if let Ok(text) = fs::read_to_string(path.join("cgroup.procs")) {
+            for pid in text.split_whitespace() {
+                if let Ok(pid) = pid.parse() {
+                    unsafe { libc::kill(pid, libc::SIGKILL) };
+                }
+            }
+        }
+        match fs::remove_dir(path) {
+            Ok(_) => return,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return,
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    warn!("failed to remove cgroup `{:?}`", path);
+}
+
+/// cpu.max 的内容，cpus 为 0（未绑定 CPU）时按一个 CPU 计算
+fn cpu_max(cpus: usize) -> String {
+    format!("{} {}", CPU_PERIOD * cpus.max(1), CPU_PERIOD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_max() {
+        assert_eq!(cpu_max(0), "100000 100000");
+        assert_eq!(cpu_max(1), "100000 100000");
+        assert_eq!(cpu_max(4), "400000 100000");
+    }
+
+    #[test]
+    fn test_owner() {
+        assert_eq!(owner("river-1234-5"), Some(1234));
+        assert_eq!(owner("river-x-5"), None);
+        assert_eq!(owner("other-1234-5"), None);
+    }
+
+    #[test]
+    fn test_stale() {
+        // pid 被复用时，与当前进程同名的 cgroup 同样是遗留的
+        assert!(stale(process::id() as libc::pid_t));
+        assert!(!stale(1));
+    }
+
+    #[test]
+    fn test_stat() {
+        let text = "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n";
+        assert_eq!(stat(text, "usage_usec").unwrap(), Some(1500));
+        assert_eq!(stat("", "usage_usec").unwrap(), None);
+        // oom 与 oom_kill 不能混淆
+        let text = "low 0\nhigh 0\nmax 3\noom 1\noom_kill 0\n";
+        assert_eq!(stat(text, "oom_kill").unwrap(), Some(0));
+        assert!(stat("oom_kill x", "oom_kill").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use super::process::{rlimits, set_rlimits, supervise, Stdio};
+use super::{ProcessExitStatus, Sandbox, SandboxBackend};
+use crate::cpus::{cpu_set, set_affinity};
+use crate::error::{Error, Result};
+
+/// 直接在本机运行，只使用 rlimit 限制资源
+///
+/// 程序可以访问整个文件系统与网络，仅用于开发与测试，不能用于生产环境。
+pub struct LocalBackend {
+    env: BTreeMap<String, String>,
+}
+
+impl LocalBackend {
+    /// env 为程序的基础环境变量
+    pub fn new(env: BTreeMap<String, String>) -> Self {
+        LocalBackend { env }
+    }
+}
+
+#[tonic::async_trait]
+impl SandboxBackend for LocalBackend {
+    async fn spawn(&self, sandbox: &Sandbox) -> Result<ProcessExitStatus> {
+        info!("local sandbox: args = {:?}", sandbox.inner_args);
+        sandbox.copy_binds().await?;
+        let sandbox = sandbox.clone();
+        let env = sandbox.environment(&self.env);
+        match tokio::task::spawn_blocking(move || run(&sandbox, env)).await {
+            Ok(res) => res,
+            Err(e) => Err(Error::SandboxError(format!("{}", e))),
+        }
+    }
+
+    // 不隔离网络，启动时跳过自检
+    async fn self_test(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn run(sandbox: &Sandbox, env: Vec<(String, String)>) -> Result<ProcessExitStatus> {
+    let (program, args) = match sandbox.inner_args.split_first() {
+        Some(val) => val,
+        None => return Err(Error::SandboxError(String::from("empty command"))),
+    };
+    let stdio = Stdio::open(sandbox)?;
+    let rlimits = rlimits(&sandbox.limits, true);
+    let cpus = if sandbox.cpus.is_empty() {
+        None
+    } else {
+        Some(cpu_set(&sandbox.cpus))
+    };
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env_clear()
+        .envs(env)
+        .current_dir(&sandbox.workdir)
+        .stdin(stdio.stdin)
+        .stdout(stdio.stdout)
+        .stderr(stdio.stderr);
+    unsafe {
+        command.pre_exec(move || {
+            // 单独的进程组，以便超时时杀死所有子进程
+            libc::setpgid(0, 0);
+            if !set_rlimits(&rlimits) {
+                return Err(std::io::Error::last_os_error());
+            }
+            if let Some(cpus) = &cpus {
+                if !set_affinity(0, cpus) {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(Error::SandboxError(format!(
+                "command `{}` not found",
+                program
+            )))
+        }
+        Err(e) => return Err(Error::IOError(e)),
+    };
+    let exited = supervise(child.id() as libc::pid_t, sandbox.limits.wall_time(), false);
+    Ok(exited.exit_status(None, None))
+}
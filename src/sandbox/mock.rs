@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::{ProcessExitStatus, Sandbox, SandboxBackend};
+use crate::error::{Error, Result};
+
+/// 一次模拟运行的结果
+#[derive(Debug, Default, Clone)]
+pub struct MockRun {
+    pub status: ProcessExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+    // 模拟程序在工作目录中创建的文件：(文件名, 内容)
+    pub files: Vec<(String, String)>,
+    // 为 Some 时模拟 stdout 被替换为指向此路径的符号链接
+    pub stdout_link: Option<String>,
+}
+
+/// 测试用的沙盒，按顺序返回预先给定的运行结果，并记录每次运行的参数
+pub struct MockBackend {
+    runs: Mutex<VecDeque<MockRun>>,
+    spawned: Mutex<Vec<Sandbox>>,
+    workdirs: Mutex<Vec<Vec<String>>>,
+}
+
+impl MockBackend {
+    pub fn new(runs: Vec<MockRun>) -> Self {
+        MockBackend {
+            runs: Mutex::new(runs.into()),
+            spawned: Mutex::new(vec![]),
+            workdirs: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn spawned(&self) -> Vec<Sandbox> {
+        self.spawned.lock().unwrap().clone()
+    }
+
+    /// 每次运行开始时工作目录中的文件名
+    pub fn workdirs(&self) -> Vec<Vec<String>> {
+        self.workdirs.lock().unwrap().clone()
+    }
+}
+
+#[tonic::async_trait]
+impl SandboxBackend for MockBackend {
+    async fn self_test(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn spawn(&self, sandbox: &Sandbox) -> Result<ProcessExitStatus> {
+        self.spawned.lock().unwrap().push(sandbox.clone());
+        let mut files = vec![];
+        for entry in try_io!(fs::read_dir(&sandbox.workdir)) {
+            files.push(try_io!(entry).file_name().to_string_lossy().into_owned());
+        }
+        self.workdirs.lock().unwrap().push(files);
+        let run = match self.runs.lock().unwrap().pop_front() {
+            Some(run) => run,
+            None => return Err(Error::SandboxError(String::from("no more mock runs"))),
+        };
+        match &run.stdout_link {
+            Some(target) => try_io!(std::os::unix::fs::symlink(target, &sandbox.stdout)),
+            None => try_io!(fs::write(&sandbox.stdout, &run.stdout)),
+        }
+        try_io!(fs::write(&sandbox.stderr, &run.stderr));
+        for (name, content) in &run.files {
+            try_io!(fs::write(Path::new(&sandbox.workdir).join(name), content));
+        }
+        Ok(run.status)
+    }
+}
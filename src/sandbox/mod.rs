@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tokio::fs::{File, OpenOptions};
+
+use crate::config::{MemoryMode, SandboxKind, CONFIG};
+use crate::error::{Error, Result};
+
+mod cgroup;
+mod local;
+#[cfg(test)]
+mod mock;
+mod native;
+mod newbie;
+mod probe;
+mod process;
+mod seccomp;
+
+pub use local::LocalBackend;
+#[cfg(test)]
+pub use mock::{MockBackend, MockRun};
+pub use native::NativeBackend;
+pub use newbie::NewbieBackend;
+pub use probe::{probe, PROBE_ARG};
+
+// stdin 为此值时表示程序没有输入
+pub static NO_STDIN: &str = "/STDIN/";
+
+lazy_static! {
+    static ref BACKEND: Arc<dyn SandboxBackend> = match CONFIG.sandbox {
+        SandboxKind::Native => Arc::new(NativeBackend::new(
+            &CONFIG.rootfs,
+            CONFIG.cgroup != 0,
+            CONFIG.sandbox_uid,
+            CONFIG.sandbox_gid,
+            CONFIG.env.clone(),
+        )),
+        SandboxKind::Newbie => Arc::new(NewbieBackend::new(
+            &CONFIG.newbie_sandbox,
+            &CONFIG.rootfs,
+            CONFIG.cgroup,
+            CONFIG.env.clone(),
+        )),
+        SandboxKind::Local => {
+            warn!("sandbox `local` does not isolate programs, do not use it in production");
+            Arc::new(LocalBackend::new(CONFIG.env.clone()))
+        }
+    };
+}
+
+/// 启动时调用，检查并准备沙盒所需的环境
+pub fn init() -> Result<()> {
+    if CONFIG.sandbox == SandboxKind::Native && CONFIG.cgroup != 0 {
+        cgroup::init()?;
+    }
+    if CONFIG.sandbox == SandboxKind::Newbie {
+        newbie::check(&CONFIG.languages)?;
+    }
+    Ok(())
+}
+
+/// 启动时调用，确认沙盒内的程序无法访问外部网络
+pub async fn self_test() -> Result<()> {
+    if CONFIG.sandbox == SandboxKind::Local {
+        warn!("sandbox `local` does not isolate network, skip self-test");
+        return Ok(());
+    }
+    backend().self_test().await?;
+    info!("network isolation self-test passed");
+    Ok(())
+}
+
+/// 配置中选择的沙盒实现
+pub fn backend() -> Arc<dyn SandboxBackend> {
+    BACKEND.clone()
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ProcessExitStatus {
+    // CPU 时间（user + sys）
+    pub time_used: i64,
+    // 墙上时间
+    pub real_time_used: i64,
+    pub memory_used: i64,
+    pub exit_code: i64,
+    pub status: i64,
+    pub signal: i64,
+    // 被 seccomp 禁止的系统调用的名称
+    pub syscall: Option<String>,
+    // 是否因超出 cgroup 的内存限制被 OOM killer 杀死
+    pub oom_killed: bool,
+}
+
+/// 沙盒的具体实现
+///
+/// 实现需要在 `workdir` 中运行 `inner_args`，将标准输入输出重定向到给定的文件，
+/// 按照 `limits` 限制资源，并返回程序的退出状态与资源使用情况。
+/// rootfs、cgroup、基础环境变量等与单次运行无关的设置由各个实现在创建时自行决定。
+#[tonic::async_trait]
+pub trait SandboxBackend: Send + Sync {
+    async fn spawn(&self, sandbox: &Sandbox) -> Result<ProcessExitStatus>;
+
+    /// 启动时调用，确认沙盒内的程序无法访问外部网络
+    async fn self_test(&self) -> Result<()>;
+}
+
+/// 资源限制
+///
+/// 时间的单位为毫秒，内存与栈的单位为 KiB，文件大小的单位为字节，pids 为 0 时表示不限制。
+/// time 为 CPU 时间，real_time 为墙上时间，为 0 时使用默认值。
+/// memory_mode 决定如何施加内存限制以及 memory_used 的统计方式。stack 为 0 时使用系统默认值。
+#[derive(Debug, Default, Clone)]
+pub struct Limits {
+    pub time: i32,
+    pub real_time: i32,
+    pub memory: i32,
+    pub memory_mode: MemoryMode,
+    pub stack: i32,
+    pub file_size: i32,
+    pub pids: i32,
+}
+
+impl Limits {
+    pub fn new(time: i32, memory: i32, file_size: i32, pids: i32) -> Self {
+        Limits {
+            time,
+            real_time: 0,
+            memory,
+            memory_mode: MemoryMode::Cgroup,
+            stack: 0,
+            file_size,
+            pids,
+        }
+    }
+
+    /// 墙上时间限制（ms），防止程序因 sleep 或阻塞而永远不退出
+    pub fn real_time_limit(&self) -> i64 {
+        if self.real_time > 0 {
+            self.real_time.into()
+        } else {
+            i64::from(self.time) * 2 + 1000
+        }
+    }
+
+    pub fn wall_time(&self) -> Duration {
+        Duration::from_millis(self.real_time_limit() as u64)
+    }
+}
+
+/// 一次沙盒运行的参数
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    pub inner_args: Vec<String>,
+    pub workdir: String,
+    pub result: String,
+    pub stdin: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub limits: Limits,
+    // 为 Some 时使用 seccomp 过滤系统调用，其中为默认白名单之外额外允许的系统调用，目前只有 native 实现支持
+    pub seccomp: Option<Vec<String>>,
+    // 是否启用 network namespace 中的 lo，默认没有任何可用的网络
+    pub loopback: bool,
+    // 程序绑定的 CPU，为空时不绑定
+    pub cpus: Vec<usize>,
+    // 额外的环境变量，会覆盖沙盒实现的基础环境变量中的同名变量
+    pub env: BTreeMap<String, String>,
+    // 为 Some 时代替沙盒实现的 rootfs
+    pub rootfs: Option<String>,
+    // 叠加在 rootfs 之上的只读层，越靠前的层优先级越高，目前只有 native 实现支持
+    pub overlay: Vec<String>,
+    // 以只读方式放入工作目录的文件：(宿主机上的路径, 工作目录中的文件名)
+    // 路径可以是 /proc/self/fd/N，此时使用的是调用者打开的文件，spawn 返回之前需要保持打开
+    // native 实现使用 bind mount，其他实现退化为复制
+    pub binds: Vec<(String, String)>,
+}
+
+impl Sandbox {
+    pub fn new(
+        inner_args: Vec<String>,
+        workdir: String,
+        result: String,
+        stdin: String,
+        stdout: String,
+        stderr: String,
+        limits: Limits,
+    ) -> Self {
+        Sandbox {
+            inner_args,
+            workdir,
+            result,
+            stdin,
+            stdout,
+            stderr,
+            limits,
+            seccomp: None,
+            loopback: false,
+            cpus: vec![],
+            env: BTreeMap::new(),
+            rootfs: None,
+            overlay: vec![],
+            binds: vec![],
+        }
+    }
+
+    /// 不支持 bind mount 的沙盒实现将 binds 中的文件复制到工作目录中
+    ///
+    /// 只复制普通文件，目标文件必须不存在，因此不会写入工作目录中预先放置的符号链接指向的文件。
+    pub async fn copy_binds(&self) -> Result<()> {
+        for (source, name) in &self.binds {
+            if name.is_empty() || name.contains('/') {
+                return Err(Error::SandboxError(format!("invalid bind `{}`", name)));
+            }
+            let mut from = try_io!(File::open(source).await);
+            if !try_io!(from.metadata().await).is_file() {
+                return Err(Error::SandboxError(format!(
+                    "bind `{}` is not a regular file",
+                    source
+                )));
+            }
+            let mut to = try_io!(
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .custom_flags(libc::O_NOFOLLOW)
+                    .open(Path::new(&self.workdir).join(name))
+                    .await
+            );
+            try_io!(tokio::io::copy(&mut from, &mut to).await);
+        }
+        Ok(())
+    }
+
+    /// 程序的完整环境变量：base 中的变量被 env 中的同名变量覆盖
+    pub fn environment(&self, base: &BTreeMap<String, String>) -> Vec<(String, String)> {
+        let mut env = base.clone();
+        env.extend(self.env.clone());
+        env.into_iter().collect()
+    }
+
+    /// 使用给定的沙盒实现运行
+    pub async fn spawn(&self, backend: Arc<dyn SandboxBackend>) -> Result<ProcessExitStatus> {
+        // 在独立的任务中运行，评测流要求 Future 同时满足 Send 与 Sync
+        let sandbox = self.clone();
+        match tokio::spawn(async move { backend.spawn(&sandbox).await }).await {
+            Ok(res) => res,
+            Err(e) => Err(Error::SandboxError(format!("{}", e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_copy_binds() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("source");
+        std::fs::write(&source, "3\n").unwrap();
+        let workdir = dir.path().join("work");
+        std::fs::create_dir(&workdir).unwrap();
+        let mut sandbox = Sandbox::new(
+            vec![],
+            String::from(workdir.to_str().unwrap()),
+            String::new(),
+            String::from(NO_STDIN),
+            String::new(),
+            String::new(),
+            Limits::default(),
+        );
+        sandbox.binds = vec![(
+            String::from(source.to_str().unwrap()),
+            String::from("out.txt"),
+        )];
+        sandbox.copy_binds().await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(workdir.join("out.txt")).unwrap(),
+            "3\n"
+        );
+
+        // 不会写入工作目录中已有的文件或符号链接指向的文件
+        let victim = dir.path().join("victim");
+        std::fs::write(&victim, "victim").unwrap();
+        std::fs::remove_file(workdir.join("out.txt")).unwrap();
+        std::os::unix::fs::symlink(&victim, workdir.join("out.txt")).unwrap();
+        assert!(sandbox.copy_binds().await.is_err());
+        assert_eq!(std::fs::read_to_string(&victim).unwrap(), "victim");
+
+        // 只复制普通文件
+        std::fs::remove_file(workdir.join("out.txt")).unwrap();
+        sandbox.binds[0].0 = String::from(dir.path().to_str().unwrap());
+        assert!(sandbox.copy_binds().await.is_err());
+    }
+}
@@ -0,0 +1,700 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::chown;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+
+use libc::{c_char, c_int, c_uint};
+
+use super::cgroup::Cgroup;
+use super::probe;
+use super::process::{rlimits, set_rlimits, supervise, Rlimit, Stdio};
+use super::seccomp::{self, Filter};
+use super::{ProcessExitStatus, Sandbox, SandboxBackend};
+use crate::config::MemoryMode;
+use crate::cpus::{cpu_set, set_affinity};
+use crate::error::{errno_str, Error, Result};
+
+// 评测目录在 rootfs 中的挂载位置，也是程序的工作目录
+static SANDBOX_WORKDIR: &str = "/tmp";
+
+// linux/mount.h
+const OPEN_TREE_CLONE: c_uint = 1;
+const MOVE_MOUNT_F_EMPTY_PATH: c_uint = 4;
+
+// 子进程在 exec 之前失败时，通过管道告知父进程失败的步骤与 errno
+static STEPS: [&str; 14] = [
+    "join cgroup",
+    "set affinity",
+    "unshare",
+    "loopback",
+    "mount",
+    "setrlimit",
+    "ptrace",
+    "fork",
+    "mount proc",
+    "chroot",
+    "redirect",
+    "drop privileges",
+    "seccomp",
+    "exec",
+];
+
+/// 进程内实现的沙盒
+///
+/// fork 出的子进程会加入 cgroup，进入新的 mount、network、ipc、uts、pid namespace，
+/// 将评测目录挂载进 rootfs 并设置 rlimit。之后 fork 出新 pid namespace 中的 1 号进程，
+/// 它挂载只属于该 namespace 的 /proc，再 fork 出目标程序，目标程序 chroot、降低权限后执行，
+/// 看不到也无法向其他评测发送信号。1 号进程不会被自己 namespace 中的信号杀死，因此目标程序
+/// 不作为 1 号进程运行；目标程序的退出状态经由 1 号进程交给最初的子进程，由它以相同的状态退出。
+/// 父进程使用 wait4 获取资源使用情况（包括各级子进程回收的目标程序），并在超出墙上时间时杀死整个进程组。
+pub struct NativeBackend {
+    rootfs: String,
+    cgroup: bool,
+    uid: u32,
+    gid: u32,
+    env: BTreeMap<String, String>,
+}
+
+impl NativeBackend {
+    /// cgroup 为 false 时只使用 rlimit 限制资源，uid 为 0 时不降低权限，env 为程序的基础环境变量
+    pub fn new(
+        rootfs: &str,
+        cgroup: bool,
+        uid: u32,
+        gid: u32,
+        env: BTreeMap<String, String>,
+    ) -> Self {
+        NativeBackend {
+            rootfs: String::from(rootfs),
+            cgroup,
+            uid,
+            gid,
+            env,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SandboxBackend for NativeBackend {
+    async fn spawn(&self, sandbox: &Sandbox) -> Result<ProcessExitStatus> {
+        info!("native sandbox: args = {:?}", sandbox.inner_args);
+        Runner::new(self, sandbox)?.spawn(sandbox).await
+    }
+
+    // 在 exec 之前的子进程中直接探测，不依赖 rootfs 中能否运行 River 自身
+    async fn self_test(&self) -> Result<()> {
+        let (dir, sandbox) = probe::prepare()?;
+        let mut runner = Runner::new(self, &sandbox)?;
+        runner.probe = true;
+        let status = runner.spawn(&sandbox).await?;
+        probe::conclude(&status, dir.path())
+    }
+}
+
+struct Runner {
+    // exec 所需的参数，均在 fork 之前准备好，子进程中不再分配内存
+    args: Vec<CString>,
+    // 按沙盒环境变量中的 PATH 展开的程序路径，子进程 chroot 之后依次尝试
+    programs: Vec<CString>,
+    env: Vec<CString>,
+    workdir: CString,
+    rootfs: CString,
+    // 为 Some 时将各层与 rootfs 合并为只读的 overlay 挂载在 rootfs 上，其中为挂载选项
+    overlay: Option<CString>,
+    mount_workdir: CString,
+    // 只读挂载到工作目录中的文件：(宿主机上的文件的游离挂载, 挂载点)
+    binds: Vec<(MountTree, CString)>,
+    mount_proc: Option<CString>,
+    sandbox_workdir: CString,
+    cgroup: bool,
+    uid: u32,
+    gid: u32,
+    filter: Option<Filter>,
+    loopback: bool,
+    cpus: Option<libc::cpu_set_t>,
+    // 为 true 时子进程在 exec 之前进行网络探测并以探测结果退出
+    probe: bool,
+}
+
+impl Runner {
+    fn new(backend: &NativeBackend, sandbox: &Sandbox) -> Result<Runner> {
+        let args = sandbox
+            .inner_args
+            .iter()
+            .map(|arg| cstring(arg.as_bytes()))
+            .collect::<Result<Vec<_>>>()?;
+        if args.is_empty() {
+            return Err(Error::SandboxError(String::from("empty command")));
+        }
+        // 工作目录属于沙盒内的用户，其中的文件可能被替换为符号链接，输出文件必须位于工作目录之外
+        for file in &[&sandbox.stdout, &sandbox.stderr, &sandbox.result] {
+            if Path::new(file).starts_with(&sandbox.workdir) {
+                return Err(Error::SandboxError(format!(
+                    "`{}` must be outside of the workdir",
+                    file
+                )));
+            }
+        }
+        let environment = sandbox.environment(&backend.env);
+        let programs = program_paths(&sandbox.inner_args[0], &environment)
+            .iter()
+            .map(|path| cstring(path.as_bytes()))
+            .collect::<Result<Vec<_>>>()?;
+        let env = environment
+            .iter()
+            .map(|(key, value)| cstring(format!("{}={}", key, value).as_bytes()))
+            .collect::<Result<Vec<_>>>()?;
+        let rootfs = Path::new(sandbox.rootfs.as_deref().unwrap_or(&backend.rootfs));
+        // 挂载 overlay 之后 /proc 可能来自任意一层
+        let layers: Vec<&Path> = sandbox
+            .overlay
+            .iter()
+            .map(Path::new)
+            .chain(Some(rootfs))
+            .collect();
+        let proc = rootfs.join("proc");
+        let has_proc = layers.iter().any(|layer| layer.join("proc").is_dir());
+        Ok(Runner {
+            args,
+            programs,
+            env,
+            workdir: cstring(sandbox.workdir.as_bytes())?,
+            rootfs: cstring(rootfs.as_os_str().as_bytes())?,
+            overlay: if sandbox.overlay.is_empty() {
+                None
+            } else {
+                Some(cstring(overlay_options(&layers)?.as_bytes())?)
+            },
+            mount_workdir: cstring(
+                rootfs
+                    .join(SANDBOX_WORKDIR.trim_start_matches('/'))
+                    .as_os_str()
+                    .as_bytes(),
+            )?,
+            binds: sandbox
+                .binds
+                .iter()
+                .map(|(source, name)| {
+                    if name.is_empty() || name.contains('/') {
+                        return Err(Error::SandboxError(format!("invalid bind `{}`", name)));
+                    }
+                    let target = rootfs
+                        .join(SANDBOX_WORKDIR.trim_start_matches('/'))
+                        .join(name);
+                    Ok((
+                        MountTree::open(source)?,
+                        cstring(target.as_os_str().as_bytes())?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            mount_proc: if has_proc {
+                Some(cstring(proc.as_os_str().as_bytes())?)
+            } else {
+                None
+            },
+            sandbox_workdir: cstring(SANDBOX_WORKDIR.as_bytes())?,
+            cgroup: backend.cgroup,
+            uid: backend.uid,
+            gid: backend.gid,
+            filter: match &sandbox.seccomp {
+                Some(extra) => Some(Filter::new(extra, sandbox.loopback)?),
+                None => None,
+            },
+            loopback: sandbox.loopback,
+            cpus: if sandbox.cpus.is_empty() {
+                None
+            } else {
+                Some(cpu_set(&sandbox.cpus))
+            },
+            probe: false,
+        })
+    }
+
+    async fn spawn(self, sandbox: &Sandbox) -> Result<ProcessExitStatus> {
+        let sandbox = sandbox.clone();
+        match tokio::task::spawn_blocking(move || self.run(&sandbox)).await {
+            Ok(res) => res,
+            Err(e) => Err(Error::SandboxError(format!("{}", e))),
+        }
+    }
+
+    fn run(&self, sandbox: &Sandbox) -> Result<ProcessExitStatus> {
+        let limits = &sandbox.limits;
+        let cgroup = if self.cgroup {
+            // 限制地址空间时 cgroup 只用于统计内存
+            let memory = match limits.memory_mode {
+                MemoryMode::AddressSpace => 0,
+                _ => limits.memory.into(),
+            };
+            // pids 包括最初的子进程与 pid namespace 中的 1 号进程
+            let pids = if limits.pids > 0 { limits.pids + 2 } else { 0 };
+            Some(Cgroup::create(memory, pids, sandbox.cpus.len())?)
+        } else {
+            None
+        };
+        let procs = match &cgroup {
+            Some(cgroup) => cgroup.procs()?,
+            None => vec![],
+        };
+        if self.uid != 0 {
+            try_io!(chown(&sandbox.workdir, Some(self.uid), Some(self.gid)));
+        }
+        // bind mount 的挂载点必须存在
+        for (_, name) in &sandbox.binds {
+            try_io!(OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(Path::new(&sandbox.workdir).join(name)));
+        }
+        let stdio = Stdio::open(sandbox)?;
+        // 没有 cgroup 时只能限制地址空间
+        let rlimits = rlimits(
+            limits,
+            limits.memory_mode == MemoryMode::AddressSpace || !self.cgroup,
+        );
+
+        let mut argv: Vec<*const c_char> = self.args.iter().map(|arg| arg.as_ptr()).collect();
+        argv.push(ptr::null());
+        let mut envp: Vec<*const c_char> = self.env.iter().map(|env| env.as_ptr()).collect();
+        envp.push(ptr::null());
+
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(Error::IOError(std::io::Error::last_os_error()));
+        }
+        let (err_read, err_write) = (fds[0], fds[1]);
+
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            unsafe {
+                libc::close(err_read);
+                libc::close(err_write);
+            }
+            return Err(Error::IOError(std::io::Error::last_os_error()));
+        }
+        if pid == 0 {
+            unsafe {
+                self.child(
+                    &procs,
+                    [
+                        stdio.stdin.as_raw_fd(),
+                        stdio.stdout.as_raw_fd(),
+                        stdio.stderr.as_raw_fd(),
+                    ],
+                    &rlimits,
+                    &argv,
+                    &envp,
+                    err_write,
+                )
+            }
+        }
+        // 子进程也会设置，这里避免在它设置之前就按进程组等待
+        unsafe {
+            libc::setpgid(pid, pid);
+            libc::close(err_write);
+        }
+        drop(stdio);
+
+        // 被跟踪的子进程在 exec 之前就需要父进程处理 ptrace 事件，因此先等待子进程退出，
+        // 再检查它是否在 exec 之前失败
+        let exited = supervise(pid, limits.wall_time(), self.filter.is_some());
+        // exec 成功时管道因 O_CLOEXEC 被关闭，读取到 0 字节
+        let mut failure = [0i32; 2];
+        let size = read_pipe(err_read, &mut failure);
+        unsafe { libc::close(err_read) };
+        if size == mem::size_of_val(&failure) {
+            let step = STEPS.get(failure[0] as usize).unwrap_or(&"unknown");
+            return Err(Error::SandboxError(format!(
+                "{} failed: {}",
+                step,
+                errno_str(Some(failure[1]))
+            )));
+        }
+
+        let (memory_used, cpu_used, oom_killed) = match &cgroup {
+            Some(cgroup) => (
+                cgroup.memory_used()?,
+                cgroup.cpu_used()?,
+                cgroup.oom_killed()?,
+            ),
+            None => (None, None, false),
+        };
+        // cgroup 统计的内存包括页缓存，按 RSS 判定时使用 rusage 中的最大常驻内存
+        let memory_used = match limits.memory_mode {
+            MemoryMode::Rss => None,
+            _ => memory_used,
+        };
+        let mut status = exited.exit_status(memory_used, cpu_used);
+        status.oom_killed = oom_killed;
+        Ok(status)
+    }
+
+    // 只调用系统调用，fork 之后在多线程程序中是安全的
+    unsafe fn child(
+        &self,
+        procs: &[CString],
+        stdio: [c_int; 3],
+        rlimits: &[Rlimit],
+        argv: &[*const c_char],
+        envp: &[*const c_char],
+        err_fd: c_int,
+    ) -> ! {
+        let fail = |step: i32| -> ! {
+            let failure = [step, *libc::__errno_location()];
+            libc::write(
+                err_fd,
+                failure.as_ptr() as *const libc::c_void,
+                mem::size_of_val(&failure),
+            );
+            libc::_exit(127)
+        };
+        // 单独的进程组，以便超时时杀死所有子进程
+        libc::setpgid(0, 0);
+        for procs in procs {
+            let fd = libc::open(procs.as_ptr(), libc::O_WRONLY);
+            if fd < 0 || libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1) < 0 {
+                fail(0);
+            }
+            libc::close(fd);
+        }
+        if let Some(cpus) = &self.cpus {
+            if !set_affinity(0, cpus) {
+                fail(1);
+            }
+        }
+        // CLONE_NEWPID 只对之后创建的子进程生效
+        if libc::unshare(
+            libc::CLONE_NEWNS
+                | libc::CLONE_NEWNET
+                | libc::CLONE_NEWIPC
+                | libc::CLONE_NEWUTS
+                | libc::CLONE_NEWPID,
+        ) < 0
+        {
+            fail(2);
+        }
+        // 新的 network namespace 中只有未启用的 lo，只在需要时启用
+        if self.loopback && !loopback_up() {
+            fail(3);
+        }
+        // 挂载点的变化不能传播回宿主机
+        if libc::mount(
+            ptr::null(),
+            b"/\0".as_ptr() as *const c_char,
+            ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            ptr::null(),
+        ) < 0
+        {
+            fail(4);
+        }
+        if let Some(overlay) = &self.overlay {
+            if libc::mount(
+                b"overlay\0".as_ptr() as *const c_char,
+                self.rootfs.as_ptr(),
+                b"overlay\0".as_ptr() as *const c_char,
+                libc::MS_RDONLY,
+                overlay.as_ptr() as *const libc::c_void,
+            ) < 0
+            {
+                fail(4);
+            }
+        }
+        if libc::mount(
+            self.workdir.as_ptr(),
+            self.mount_workdir.as_ptr(),
+            ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            ptr::null(),
+        ) < 0
+        {
+            fail(4);
+        }
+        for (tree, target) in &self.binds {
+            if libc::syscall(
+                libc::SYS_move_mount,
+                tree.0,
+                b"\0".as_ptr() as *const c_char,
+                libc::AT_FDCWD,
+                target.as_ptr(),
+                MOVE_MOUNT_F_EMPTY_PATH,
+            ) < 0
+                || libc::mount(
+                    ptr::null(),
+                    target.as_ptr(),
+                    ptr::null(),
+                    libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                    ptr::null(),
+                ) < 0
+            {
+                fail(4);
+            }
+        }
+        // rlimit 与跟踪会被目标程序继承，RLIMIT_CORE 也避免子进程转发信号时产生 core dump
+        if !set_rlimits(rlimits) {
+            fail(5);
+        }
+        // 由子进程请求跟踪，fork 出的目标程序会被父进程自动跟踪
+        if self.filter.is_some() && !seccomp::trace_me() {
+            fail(6);
+        }
+        // 1 号进程在此记录目标程序的退出状态，-1 表示没有记录
+        let exit_status = libc::mmap(
+            ptr::null_mut(),
+            mem::size_of::<c_int>(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        ) as *mut c_int;
+        if exit_status as *mut libc::c_void == libc::MAP_FAILED {
+            fail(7);
+        }
+        ptr::write_volatile(exit_status, -1);
+        let init = libc::fork();
+        if init < 0 {
+            fail(7);
+        }
+        if init > 0 {
+            forward_exit(init, exit_status);
+        }
+        // 新 pid namespace 中的 1 号进程，挂载的 /proc 中只有这个 namespace 中的进程
+        if let Some(proc) = &self.mount_proc {
+            if libc::mount(
+                b"proc\0".as_ptr() as *const c_char,
+                proc.as_ptr(),
+                b"proc\0".as_ptr() as *const c_char,
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                ptr::null(),
+            ) < 0
+            {
+                fail(8);
+            }
+        }
+        let pid = libc::fork();
+        if pid < 0 {
+            fail(7);
+        }
+        if pid > 0 {
+            reap(pid, exit_status);
+        }
+        if libc::chroot(self.rootfs.as_ptr()) < 0 || libc::chdir(self.sandbox_workdir.as_ptr()) < 0
+        {
+            fail(9);
+        }
+        for (fd, target) in stdio.iter().zip(0..) {
+            if libc::dup2(*fd, target) < 0 {
+                fail(10);
+            }
+        }
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) < 0 {
+            fail(11);
+        }
+        if self.uid != 0
+            && (libc::setgroups(0, ptr::null()) < 0
+                || libc::setgid(self.gid) < 0
+                || libc::setuid(self.uid) < 0)
+        {
+            fail(11);
+        }
+        if let Some(filter) = &self.filter {
+            if !filter.install() {
+                fail(12);
+            }
+        }
+        if self.probe {
+            libc::_exit(probe::probe());
+        }
+        // 不能使用 execvpe，它按 River 自身而不是沙盒的 PATH 查找程序
+        for program in &self.programs {
+            libc::execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr());
+        }
+        fail(13)
+    }
+}
+
+/// 宿主机上的文件的游离副本，由父进程在 fork 之前创建
+///
+/// 子进程进入新的 mount namespace 之后，无法再以其他 namespace 中的挂载（例如
+/// /proc/self/fd/N 指向的文件）为源进行 bind mount，而游离的挂载可以移动到任意位置。
+/// 源为 /proc/self/fd/N 时挂载的是该文件描述符指向的文件，而不会重新按路径查找。
+struct MountTree(c_int);
+
+impl MountTree {
+    fn open(source: &str) -> Result<MountTree> {
+        let path = cstring(source.as_bytes())?;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_open_tree,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                OPEN_TREE_CLONE | libc::O_CLOEXEC as c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::SandboxError(format!(
+                "bind `{}` failed: {}",
+                source,
+                errno_str(std::io::Error::last_os_error().raw_os_error())
+            )));
+        }
+        Ok(MountTree(fd as c_int))
+    }
+}
+
+impl Drop for MountTree {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+// pid namespace 中的 1 号进程：回收 namespace 中的所有进程，目标程序退出时记录它的状态后退出，
+// namespace 中剩余的进程随之被内核杀死。只调用系统调用
+unsafe fn reap(pid: libc::pid_t, exit_status: *mut c_int) -> ! {
+    loop {
+        let mut status = 0;
+        let child = libc::waitpid(-1, &mut status, 0);
+        if child == pid {
+            ptr::write_volatile(exit_status, status);
+            libc::_exit(0);
+        }
+        if child < 0 && *libc::__errno_location() != libc::EINTR {
+            libc::_exit(127);
+        }
+    }
+}
+
+// pid namespace 之外的子进程：等待 1 号进程退出，并以目标程序的退出码或信号退出。只调用系统调用
+unsafe fn forward_exit(init: libc::pid_t, exit_status: *const c_int) -> ! {
+    let mut status = 0;
+    while libc::waitpid(init, &mut status, 0) < 0 {
+        if *libc::__errno_location() != libc::EINTR {
+            libc::_exit(127);
+        }
+    }
+    // 没有记录时 1 号进程在目标程序退出之前就失败或被杀死了，使用它自己的状态
+    let recorded = ptr::read_volatile(exit_status);
+    if recorded >= 0 {
+        status = recorded;
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, ptr::null_mut());
+        libc::kill(libc::getpid(), signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+// 启用 lo，只调用系统调用
+unsafe fn loopback_up() -> bool {
+    let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if fd < 0 {
+        return false;
+    }
+    let mut req: libc::ifreq = mem::zeroed();
+    req.ifr_name[0] = b'l' as c_char;
+    req.ifr_name[1] = b'o' as c_char;
+    let ok = libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut req) == 0 && {
+        req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        libc::ioctl(fd, libc::SIOCSIFFLAGS, &req) == 0
+    };
+    libc::close(fd);
+    ok
+}
+
+// 没有 upperdir 的 overlay 是只读的，越靠前的层优先级越高
+fn overlay_options(layers: &[&Path]) -> Result<String> {
+    let mut lowerdir = vec![];
+    for layer in layers {
+        let layer = layer.to_string_lossy();
+        // 挂载选项使用 `,` 与 `:` 分隔
+        if layer.contains(',') || layer.contains(':') {
+            return Err(Error::SandboxError(format!(
+                "invalid overlay layer `{}`",
+                layer
+            )));
+        }
+        lowerdir.push(layer);
+    }
+    Ok(format!("lowerdir={}", lowerdir.join(":")))
+}
+
+// 程序名不含 `/` 时按 PATH 展开为各个候选路径，与 execvpe 相同，PATH 中的空项表示当前目录，
+// 没有 PATH 时使用 glibc 的默认值
+fn program_paths(program: &str, env: &[(String, String)]) -> Vec<String> {
+    if program.contains('/') {
+        return vec![String::from(program)];
+    }
+    let path = env
+        .iter()
+        .find(|(key, _)| key == "PATH")
+        .map(|(_, value)| value.as_str())
+        .unwrap_or("/bin:/usr/bin");
+    path.split(':')
+        .map(|dir| {
+            if dir.is_empty() {
+                format!("./{}", program)
+            } else {
+                format!("{}/{}", dir.trim_end_matches('/'), program)
+            }
+        })
+        .collect()
+}
+
+fn cstring(value: &[u8]) -> Result<CString> {
+    match CString::new(value) {
+        Ok(val) => Ok(val),
+        Err(e) => Err(Error::StringToCStringError(e)),
+    }
+}
+
+fn read_pipe(fd: c_int, buffer: &mut [i32; 2]) -> usize {
+    let mut size = 0;
+    let total = mem::size_of_val(buffer);
+    while size < total {
+        let n = unsafe {
+            libc::read(
+                fd,
+                (buffer.as_mut_ptr() as *mut u8).add(size) as *mut libc::c_void,
+                total - size,
+            )
+        };
+        if n < 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+            continue;
+        }
+        if n <= 0 {
+            break;
+        }
+        size += n as usize;
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_paths() {
+        let env = vec![(String::from("PATH"), String::from("/usr/bin/::/bin"))];
+        assert_eq!(
+            program_paths("python3", &env),
+            vec!["/usr/bin/python3", "./python3", "/bin/python3"]
+        );
+        assert_eq!(program_paths("./main", &env), vec!["./main"]);
+        assert_eq!(
+            program_paths("java", &[]),
+            vec!["/bin/java", "/usr/bin/java"]
+        );
+    }
+}
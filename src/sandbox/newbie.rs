@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+
+use tokio::fs::read_to_string;
+use tokio::process::Command;
+
+use super::probe;
+use super::{ProcessExitStatus, Sandbox, SandboxBackend};
+use crate::config::LanguageConf;
+use crate::cpus::{cpu_set, set_affinity};
+use crate::error::{Error, Result};
+
+/// 通过外部的 newbie-sandbox 程序运行，运行结果以 `key = value` 的形式写入 result 文件
+pub struct NewbieBackend {
+    binary: String,
+    rootfs: String,
+    cgroup: i32,
+    env: BTreeMap<String, String>,
+}
+
+impl NewbieBackend {
+    pub fn new(binary: &str, rootfs: &str, cgroup: i32, env: BTreeMap<String, String>) -> Self {
+        NewbieBackend {
+            binary: String::from(binary),
+            rootfs: String::from(rootfs),
+            cgroup,
+            env,
+        }
+    }
+}
+
+/// 启动时调用：newbie-sandbox 不过滤系统调用，拒绝要求 seccomp 的语言，而不是在运行时静默忽略
+pub fn check(languages: &HashMap<String, LanguageConf>) -> Result<()> {
+    let mut names: Vec<&String> = languages
+        .iter()
+        .filter(|(_, lang)| lang.seccomp)
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    if let Some(name) = names.first() {
+        return Err(Error::SandboxError(format!(
+            "newbie-sandbox does not support seccomp, set `seccomp: false` for language `{}`",
+            name
+        )));
+    }
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl SandboxBackend for NewbieBackend {
+    async fn self_test(&self) -> Result<()> {
+        probe::check(self).await
+    }
+
+    async fn spawn(&self, sandbox: &Sandbox) -> Result<ProcessExitStatus> {
+        if !sandbox.overlay.is_empty() {
+            return Err(Error::SandboxError(String::from(
+                "newbie-sandbox does not support overlay",
+            )));
+        }
+        if sandbox.limits.stack != 0 {
+            return Err(Error::SandboxError(String::from(
+                "newbie-sandbox does not support stack limit",
+            )));
+        }
+        if sandbox.loopback {
+            return Err(Error::SandboxError(String::from(
+                "newbie-sandbox does not support localhost",
+            )));
+        }
+        if sandbox.seccomp.is_some() {
+            return Err(Error::SandboxError(String::from(
+                "newbie-sandbox does not support seccomp",
+            )));
+        }
+        sandbox.copy_binds().await?;
+        let mut args = vec![
+            String::from(&self.binary),
+            String::from("-w"),
+            String::from(&sandbox.workdir),
+            String::from("--rootfs"),
+            String::from(sandbox.rootfs.as_deref().unwrap_or(&self.rootfs)),
+            String::from("-r"),
+            String::from(&sandbox.result),
+            String::from("-i"),
+            String::from(&sandbox.stdin),
+            String::from("-o"),
+            String::from(&sandbox.stdout),
+            String::from("-e"),
+            String::from(&sandbox.stderr),
+            String::from("-t"),
+            sandbox.limits.time.to_string(),
+            String::from("-m"),
+            sandbox.limits.memory.to_string(),
+            String::from("-f"),
+            sandbox.limits.file_size.to_string(),
+            String::from("-c"),
+            self.cgroup.to_string(),
+            String::from("-p"),
+            sandbox.limits.pids.to_string(),
+            String::from("--"),
+        ];
+        args.extend_from_slice(&sandbox.inner_args);
+        info!("args = {:?}", args.join(" "));
+        let mut command = Command::new(&args[0]);
+        // newbie-sandbox 将自身的环境变量传给程序
+        command
+            .args(&args[1..])
+            .env_clear()
+            .envs(sandbox.environment(&self.env));
+        if !sandbox.cpus.is_empty() {
+            // newbie-sandbox 创建的子进程会继承绑定的 CPU
+            let cpus = cpu_set(&sandbox.cpus);
+            unsafe {
+                command.pre_exec(move || {
+                    if !set_affinity(0, &cpus) {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(Error::SandboxNotFound(String::from(&args[0])))
+            }
+            Err(e) => return Err(Error::IOError(e)),
+        };
+        let exit_status = try_io!(child.wait().await);
+        if !exit_status.success() {
+            return Err(Error::SandboxError(String::from("run sandbox error!")));
+        }
+
+        let mut time_used = 0;
+        // 旧版本的 newbie-sandbox 不提供墙上时间
+        let mut real_time_used = 0;
+        let mut memory_used = 0;
+        let mut exit_code = 0;
+        let mut status = 0;
+        let mut signal = 0;
+
+        let text = try_io!(read_to_string(&sandbox.result).await);
+        for line in text.split('\n') {
+            if !line.contains('=') {
+                continue;
+            }
+            let mut splitter = line.splitn(2, " = ");
+            let key = if let Some(s) = splitter.next() {
+                s
+            } else {
+                return Err(Error::StringSplitError());
+            };
+            let value = if let Some(s) = splitter.next() {
+                s
+            } else {
+                return Err(Error::StringSplitError());
+            };
+            match key {
+                "time_used" => time_used = string_to_i64(value)?,
+                "real_time_used" => real_time_used = string_to_i64(value)?,
+                "memory_used" => memory_used = string_to_i64(value)?,
+                "exit_code" => exit_code = string_to_i64(value)?,
+                "status" => status = string_to_i64(value)?,
+                "signal" => signal = string_to_i64(value)?,
+                _ => continue,
+            }
+            debug!("{}: {}", key, value);
+        }
+
+        Ok(ProcessExitStatus {
+            time_used,
+            real_time_used,
+            memory_used,
+            exit_code,
+            status,
+            signal,
+            syscall: None,
+            oom_killed: false,
+        })
+    }
+}
+
+fn string_to_i64(value: &str) -> Result<i64> {
+    if let Ok(res) = value.parse() {
+        return Ok(res);
+    }
+    Err(Error::StringToIntError(String::from(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::sandbox::Limits;
+
+    #[test]
+    fn test_check() {
+        let mut config: Config =
+            serde_yaml::from_str(include_str!("../../config.template.yaml")).unwrap();
+        assert!(check(&config.languages).is_err());
+        for lang in config.languages.values_mut() {
+            lang.seccomp = false;
+        }
+        assert!(check(&config.languages).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported() {
+        let backend = NewbieBackend::new("/nonexistent", "/", 0, BTreeMap::new());
+        let sandbox = || {
+            Sandbox::new(
+                vec![String::from("./main")],
+                String::from("/nonexistent"),
+                String::from("result.txt"),
+                String::from("stdin.txt"),
+                String::from("stdout.txt"),
+                String::from("stderr.txt"),
+                Limits::new(1000, 65536, 1024, 16),
+            )
+        };
+        let mut localhost = sandbox();
+        localhost.loopback = true;
+        let mut seccomp = sandbox();
+        seccomp.seccomp = Some(vec![]);
+        for sandbox in &[localhost, seccomp] {
+            match backend.spawn(sandbox).await {
+                Err(Error::SandboxError(msg)) => assert!(msg.contains("does not support")),
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! 网络隔离自检
+//!
+//! native 沙盒在 exec 之前的子进程中直接探测；其他沙盒无法在其中运行 River 的代码，
+//! 启动时将 River 自身复制到评测目录中，以 `PROBE_ARG` 参数在沙盒内运行。
+//! 检查沙盒内的程序是否能够访问外部网络。
+
+use std::env;
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use tempfile::{tempdir_in, TempDir};
+
+use super::{Limits, ProcessExitStatus, Sandbox, SandboxBackend, NO_STDIN};
+use crate::config::{CONFIG, RUN_DIRNAME, STDERR_FILENAME, STDOUT_FILENAME};
+use crate::error::{Error, Result};
+
+pub static PROBE_ARG: &str = "--network-probe";
+static PROBE_FILENAME: &str = "network-probe";
+// 存在通往外部的路由时探测程序的退出码，与动态链接失败等情况的退出码区分开
+static REACHABLE: i32 = 42;
+
+/// 在沙盒内执行：存在通往外部的路由时以 `REACHABLE` 退出
+///
+/// 只调用系统调用，可以在 fork 之后的子进程中直接调用。
+/// 探测用的外部地址为 8.8.8.8 与 2001:4860:4860::8888，UDP connect 只做路由查询，不会真正发出数据包。
+pub fn probe() -> i32 {
+    unsafe {
+        let mut v4: libc::sockaddr_in = mem::zeroed();
+        v4.sin_family = libc::AF_INET as libc::sa_family_t;
+        v4.sin_port = 53u16.to_be();
+        v4.sin_addr.s_addr = u32::from_be_bytes([8, 8, 8, 8]).to_be();
+        let mut v6: libc::sockaddr_in6 = mem::zeroed();
+        v6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        v6.sin6_port = 53u16.to_be();
+        v6.sin6_addr.s6_addr = [
+            0x20, 0x01, 0x48, 0x60, 0x48, 0x60, 0, 0, 0, 0, 0, 0, 0, 0, 0x88, 0x88,
+        ];
+        if reachable(
+            libc::AF_INET,
+            &v4 as *const _ as *const libc::sockaddr,
+            mem::size_of_val(&v4),
+        ) || reachable(
+            libc::AF_INET6,
+            &v6 as *const _ as *const libc::sockaddr,
+            mem::size_of_val(&v6),
+        ) {
+            return REACHABLE;
+        }
+    }
+    0
+}
+
+unsafe fn reachable(family: libc::c_int, addr: *const libc::sockaddr, len: usize) -> bool {
+    let fd = libc::socket(family, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if fd < 0 {
+        return false;
+    }
+    let ok = libc::connect(fd, addr, len as libc::socklen_t) == 0;
+    libc::close(fd);
+    ok
+}
+
+/// 创建探测使用的目录与沙盒，程序的输出保存在工作目录之外
+pub fn prepare() -> Result<(TempDir, Sandbox)> {
+    let dir = match tempdir_in(&CONFIG.judge_dir) {
+        Ok(val) => val,
+        Err(e) => return Err(Error::CreateTempDirError(e)),
+    };
+    try_io!(fs::create_dir(dir.path().join(RUN_DIRNAME)));
+    let path = |name: &str| String::from(dir.path().join(name).to_string_lossy());
+    let sandbox = Sandbox::new(
+        vec![format!("./{}", PROBE_FILENAME), String::from(PROBE_ARG)],
+        path(RUN_DIRNAME),
+        path("result.txt"),
+        String::from(NO_STDIN),
+        path(STDOUT_FILENAME),
+        path(STDERR_FILENAME),
+        Limits::new(5000, 256 * 1024, 1024 * 1024, 8),
+    );
+    Ok((dir, sandbox))
+}
+
+/// 根据探测程序的退出状态判断沙盒内能否访问外部网络
+pub fn conclude(status: &ProcessExitStatus, dir: &Path) -> Result<()> {
+    match (status.exit_code, status.signal) {
+        (0, 0) => Ok(()),
+        (code, 0) if code == REACHABLE as i64 => Err(Error::SandboxError(String::from(
+            "network isolation self-test failed: sandboxed program can reach the network",
+        ))),
+        _ => Err(Error::SandboxError(format!(
+            "network isolation self-test could not run: exit code {}, signal {}, stderr `{}`",
+            status.exit_code,
+            status.signal,
+            fs::read_to_string(dir.join(STDERR_FILENAME))
+                .unwrap_or_default()
+                .trim()
+        ))),
+    }
+}
+
+/// 将 River 自身作为探测程序在沙盒中运行，沙盒内可以访问外部网络或探测程序无法运行时返回错误
+///
+/// rootfs 中需要有 River 所依赖的动态库。
+pub async fn check(backend: &dyn SandboxBackend) -> Result<()> {
+    let (dir, sandbox) = prepare()?;
+    let exe = try_io!(env::current_exe());
+    try_io!(fs::copy(
+        exe,
+        dir.path().join(RUN_DIRNAME).join(PROBE_FILENAME)
+    ));
+    let status = backend.spawn(&sandbox).await?;
+    conclude(&status, dir.path())
+}
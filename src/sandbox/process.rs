@@ -0,0 +1,163 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::c_int;
+
+use super::seccomp::{self, syscall_name};
+use super::{Limits, ProcessExitStatus, Sandbox, NO_STDIN};
+use crate::error::Result;
+
+pub type Rlimit = (libc::__rlimit_resource_t, libc::rlim_t);
+
+/// 程序的标准输入输出，由父进程打开后交给子进程
+pub struct Stdio {
+    pub stdin: File,
+    pub stdout: File,
+    pub stderr: File,
+}
+
+impl Stdio {
+    pub fn open(sandbox: &Sandbox) -> Result<Stdio> {
+        let stdin = if sandbox.stdin == NO_STDIN {
+            try_io!(File::open("/dev/null"))
+        } else {
+            try_io!(File::open(&sandbox.stdin))
+        };
+        Ok(Stdio {
+            stdin,
+            stdout: try_io!(create(&sandbox.stdout)),
+            stderr: try_io!(create(&sandbox.stderr)),
+        })
+    }
+}
+
+fn create(path: &str) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+/// 需要为子进程设置的 rlimit，没有 cgroup 时使用地址空间限制内存
+pub fn rlimits(limits: &Limits, address_space: bool) -> Vec<Rlimit> {
+    let mut rlimits = vec![
+        // CPU 时间限制以秒为单位，多留出一秒，由父进程根据实际用时判断是否超时
+        (
+            libc::RLIMIT_CPU,
+            (limits.time as libc::rlim_t).div_ceil(1000) + 1,
+        ),
+        (libc::RLIMIT_FSIZE, limits.file_size as libc::rlim_t),
+        (libc::RLIMIT_CORE, 0),
+    ];
+    if address_space {
+        rlimits.push((libc::RLIMIT_AS, limits.memory as libc::rlim_t * 1024));
+    }
+    if limits.stack > 0 {
+        rlimits.push((libc::RLIMIT_STACK, limits.stack as libc::rlim_t * 1024));
+    }
+    rlimits
+}
+
+/// 在子进程中设置 rlimit，只调用系统调用
+pub unsafe fn set_rlimits(rlimits: &[Rlimit]) -> bool {
+    for (resource, value) in rlimits {
+        let limit = libc::rlimit {
+            rlim_cur: *value,
+            rlim_max: *value,
+        };
+        if libc::setrlimit(*resource, &limit) < 0 {
+            return false;
+        }
+    }
+    true
+}
+
+/// 子进程退出时的状态
+pub struct Exited {
+    pub status: c_int,
+    pub rusage: libc::rusage,
+    // 被杀死时不小于墙上时间限制
+    pub real_time: i64,
+    // 被 seccomp 禁止的系统调用
+    pub syscall: Option<libc::c_long>,
+}
+
+impl Exited {
+    /// memory_used 与 cpu_used 为 None 时使用 rusage 中的最大常驻内存与 CPU 时间
+    pub fn exit_status(
+        &self,
+        memory_used: Option<i64>,
+        cpu_used: Option<i64>,
+    ) -> ProcessExitStatus {
+        let time_used = cpu_used.unwrap_or_else(|| {
+            timeval_ms(&self.rusage.ru_utime) + timeval_ms(&self.rusage.ru_stime)
+        });
+        let (exit_code, signal) = if libc::WIFSIGNALED(self.status) {
+            (0, libc::WTERMSIG(self.status))
+        } else {
+            (libc::WEXITSTATUS(self.status), 0)
+        };
+        ProcessExitStatus {
+            time_used,
+            real_time_used: self.real_time,
+            memory_used: memory_used.unwrap_or(self.rusage.ru_maxrss),
+            exit_code: exit_code.into(),
+            status: self.status.into(),
+            signal: signal.into(),
+            syscall: self.syscall.map(syscall_name),
+            oom_killed: false,
+        }
+    }
+}
+
+/// 等待子进程退出，超出墙上时间时杀死它所在的进程组
+///
+/// 子进程需要通过 setpgid(0, 0) 创建自己的进程组。traced 为 true 时子进程需要调用
+/// `seccomp::trace_me`，由父进程处理 ptrace 事件。
+pub fn supervise(pid: libc::pid_t, wall_time: Duration, traced: bool) -> Exited {
+    let start = Instant::now();
+    let (done, timer) = mpsc::channel::<()>();
+    let watchdog = thread::spawn(move || {
+        if timer.recv_timeout(wall_time).is_err() {
+            unsafe { libc::kill(-pid, libc::SIGKILL) };
+        }
+    });
+    let (status, rusage, syscall) = if traced {
+        seccomp::wait(pid)
+    } else {
+        let (status, rusage) = wait(pid);
+        (status, rusage, None)
+    };
+    let real_time = start.elapsed().as_millis() as i64;
+    let _ = done.send(());
+    let _ = watchdog.join();
+    // 清理程序遗留的子进程
+    unsafe { libc::kill(-pid, libc::SIGKILL) };
+    Exited {
+        status,
+        rusage,
+        real_time,
+        syscall,
+    }
+}
+
+pub fn wait(pid: libc::pid_t) -> (c_int, libc::rusage) {
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+    loop {
+        let res = unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) };
+        if res < 0 && io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+            continue;
+        }
+        return (status, rusage);
+    }
+}
+
+fn timeval_ms(time: &libc::timeval) -> i64 {
+    time.tv_sec * 1000 + time.tv_usec / 1000
+}
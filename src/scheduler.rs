@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use tokio::sync::{watch, Semaphore, SemaphorePermit};
+
+use crate::config::CONFIG;
+use crate::cpus;
+use crate::error::{Error, Result};
+use crate::progress::Progress;
+use crate::result::pending;
+
+lazy_static! {
+    // 每次运行独占一组 CPU，最大并发量即为 CPU 组数
+    pub static ref SCHEDULER: Scheduler = Scheduler::new(slots());
+}
+
+#[cfg(not(test))]
+fn slots() -> Vec<Vec<usize>> {
+    let available = cpus::available();
+    let slots = cpus::allocate(
+        &available,
+        cpus::siblings,
+        &CONFIG.reserved_cpus,
+        CONFIG.exclude_smt,
+        CONFIG.cpus_per_run,
+    );
+    info!("cpus = {:?}, slots = {:?}", available, slots);
+    slots
+}
+
+// 测试中没有配置文件，使用默认设置
+#[cfg(test)]
+fn slots() -> Vec<Vec<usize>> {
+    cpus::allocate(&cpus::available(), cpus::siblings, &[], false, 1)
+}
+
+/// 启动时调用：划分 CPU，并将 River 自身绑定到保留的 CPU 上
+pub fn init() -> Result<()> {
+    // 必须在绑定之前划分，否则只能看到保留的 CPU
+    if SCHEDULER.capacity == 0 {
+        return Err(Error::CustomError(String::from(
+            "no cpu left for judging, check `reserved_cpus` and `cpus_per_run`",
+        )));
+    }
+    if !CONFIG.reserved_cpus.is_empty() {
+        cpus::pin_process(&CONFIG.reserved_cpus)?;
+    }
+    Ok(())
+}
+
+/// 评测任务调度器
+///
+/// 在信号量的基础上记录排队中的任务，以便向客户端报告真实的排队位置与预计等待时间。
+/// tokio 的信号量是公平的（先进先出），因此排队位置即为之前排队且尚未获得许可的任务数量。
+pub struct Scheduler {
+    semaphore: Semaphore,
+    capacity: usize,
+    // 空闲的 CPU 组，数量与信号量剩余的许可相同
+    free: Mutex<Vec<Vec<usize>>>,
+    ticket: AtomicU64,
+    waiting: Mutex<BTreeSet<u64>>,
+    // 排队情况发生变化时通知所有等待者
+    changed: watch::Sender<u64>,
+    // 单次运行耗时的滑动平均值（毫秒），0 表示尚无数据
+    average: Mutex<f64>,
+}
+
+pub struct Permit<'a> {
+    _permit: SemaphorePermit<'a>,
+    scheduler: &'a Scheduler,
+    started: Instant,
+    cpus: Vec<usize>,
+}
+
+struct Waiter<'a> {
+    scheduler: &'a Scheduler,
+    ticket: u64,
+}
+
+impl Scheduler {
+    /// 每个 CPU 组对应一个许可，CPU 组为空时不绑定 CPU
+    pub fn new(slots: Vec<Vec<usize>>) -> Self {
+        let (changed, _) = watch::channel(0);
+        Scheduler {
+            semaphore: Semaphore::new(slots.len()),
+            capacity: slots.len(),
+            // 后进先出，优先复用刚释放的 CPU
+            free: Mutex::new(slots.into_iter().rev().collect()),
+            ticket: AtomicU64::new(0),
+            waiting: Mutex::new(BTreeSet::new()),
+            changed,
+            average: Mutex::new(0.0),
+        }
+    }
+
+    /// 获取一个运行许可，等待期间持续报告排队位置
+    pub async fn acquire(&self, progress: &Progress) -> Permit<'_> {
+        if let Ok(permit) = self.semaphore.try_acquire() {
+            return self.permit(permit);
+        }
+        let waiter = self.enqueue();
+        let mut changed = self.changed.subscribe();
+        let acquire = self.semaphore.acquire();
+        tokio::pin!(acquire);
+        let mut reported = 0;
+        let permit = loop {
+            let position = waiter.position();
+            if position != reported {
+                reported = position;
+                progress.send(pending(position as i32, self.estimate(position)));
+            }
+            tokio::select! {
+                permit = &mut acquire => break permit,
+                _ = changed.changed() => {}
+            }
+        };
+        drop(waiter);
+        // 信号量永远不会被关闭
+        self.permit(permit.unwrap())
+    }
+
+    fn permit<'a>(&'a self, permit: SemaphorePermit<'a>) -> Permit<'a> {
+        // 持有许可时一定有空闲的 CPU 组
+        let cpus = self.free.lock().unwrap().pop().unwrap_or_default();
+        Permit {
+            _permit: permit,
+            scheduler: self,
+            started: Instant::now(),
+            cpus,
+        }
+    }
+
+    fn enqueue(&self) -> Waiter<'_> {
+        let ticket = self.ticket.fetch_add(1, Ordering::SeqCst);
+        self.waiting.lock().unwrap().insert(ticket);
+        self.changed.send_modify(|v| *v += 1);
+        Waiter {
+            scheduler: self,
+            ticket,
+        }
+    }
+
+    /// 根据排队位置估算等待时间（毫秒）
+    fn estimate(&self, position: usize) -> i64 {
+        let average = *self.average.lock().unwrap();
+        let rounds = position.div_ceil(self.capacity);
+        (average * rounds as f64) as i64
+    }
+
+    fn record(&self, elapsed: f64) {
+        let mut average = self.average.lock().unwrap();
+        *average = if *average == 0.0 {
+            elapsed
+        } else {
+            *average * 0.8 + elapsed * 0.2
+        };
+    }
+}
+
+impl Permit<'_> {
+    /// 本次运行独占的 CPU
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+}
+
+impl Drop for Permit<'_> {
+    // 先归还 CPU 组，信号量的许可随后才会释放
+    fn drop(&mut self) {
+        self.scheduler
+            .free
+            .lock()
+            .unwrap()
+            .push(std::mem::take(&mut self.cpus));
+        self.scheduler
+            .record(self.started.elapsed().as_millis() as f64);
+    }
+}
+
+impl Waiter<'_> {
+    /// 当前任务在队列中的位置，从 1 开始
+    fn position(&self) -> usize {
+        self.scheduler
+            .waiting
+            .lock()
+            .unwrap()
+            .range(..=self.ticket)
+            .count()
+    }
+}
+
+impl Drop for Waiter<'_> {
+    // 获得许可或请求被取消时都需要离开队列
+    fn drop(&mut self) {
+        self.scheduler.waiting.lock().unwrap().remove(&self.ticket);
+        self.scheduler.changed.send_modify(|v| *v += 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::river::judge_response::State;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_queue_position() {
+        let scheduler = Scheduler::new(vec![vec![0]]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let progress = Progress::new(tx);
+        let first = scheduler.acquire(&progress).await;
+        // 有空闲许可时不会报告排队
+        assert!(rx.try_recv().is_err());
+
+        let second = scheduler.acquire(&progress);
+        tokio::pin!(second);
+        tokio::select! {
+            _ = &mut second => unreachable!(),
+            _ = tokio::task::yield_now() => {}
+        }
+        match rx.try_recv().unwrap().state {
+            Some(State::Pending(status)) => assert_eq!(status.position, 1),
+            _ => unreachable!(),
+        }
+        drop(first);
+        let _second = second.await;
+        assert_eq!(scheduler.waiting.lock().unwrap().len(), 0);
+    }
+}